@@ -2,7 +2,39 @@ use std::path::{ PathBuf };
 use std::fs;
 use std::env;
 
+use dock_gen::builder::DockerfileBuilder;
 use dock_gen::generator::{DockerfileGenerator, GenerateError};
+use dock_gen::provenance::Provenance;
+use dock_gen::size::SizeTable;
+
+// Base images the example could be built on instead
+pub const CANDIDATES : [&str; 2] = ["python:3.7-alpine", "gcr.io/distroless/python3"];
+
+pub fn dockerfile(py_version : i32) -> DockerfileBuilder {
+    DockerfileGenerator::builder()
+        .comment("Use an official Python runtime as a parent image")
+        .when_else(py_version == 2,
+                   |b| b.from("python:2.7-slim"),
+                   |b| b.from("python:3.7-slim"))
+        .empty_line()
+        .comment("Set the working directory to /app")
+        .work_dir("/app")
+        .empty_line()
+        .comment("Copy the current directory contents into the container at /app")
+        .copy(".", "/app")
+        .empty_line()
+        .comment("Install any needed packages specified in requirements.txt")
+        .run("pip install --trusted-host pypi.python.org -r requirements.txt")
+        .empty_line()
+        .comment("Make port 80 available to the world outside this container")
+        .expose(80)
+        .empty_line()
+        .comment("Define environment variable")
+        .env("NAME", "World")
+        .empty_line()
+        .comment("Run app.py when the container launches")
+        .cmd(r#"["python", "app.py"]"#)
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -12,8 +44,8 @@ fn main() {
     }
 
     let py_version = match args[1].parse::<i32>() {
-        Ok(version) => version,
-        Err(_) => {
+        Ok(version) if version == 2 || version == 3 => version,
+        _ => {
             println!("Invalid argument, expect python version (2 or 3)");
             return
         },
@@ -28,44 +60,24 @@ fn main() {
         Ok(_) => println!("File {} erased", docker_file_path.to_str().unwrap()),
     }
 
-    let mut dock_generator = DockerfileGenerator::default();
-    dock_generator.path(docker_file_path);
-
-    if py_version == 2 {
-        dock_generator.comment("Use an official Python runtime as a parent image")
-            .from("python:2.7-slim");
-    } else if py_version == 3 {
-        dock_generator.comment("Use an official Python runtime as a parent image")
-            .from("python:3.7-slim");
-    }
-
-    dock_generator.empty_line()
-        .comment("Set the working directory to /app")
-        .work_dir("/app")
-        .empty_line()
-        .comment("Copy the current directory contents into the container at /app")
-        .copy(".", "/app")
-        .empty_line()
-        .comment("Install any needed packages specified in requirements.txt")
-        .run("pip install --trusted-host pypi.python.org -r requirements.txt")
-        .empty_line()
-        .comment("Make port 80 available to the world outside this container")
-        .expose(80)
-        .empty_line()
-        .comment("Define environment variable")
-        .env("NAME", "World")
-        .empty_line()
-        .comment("Run app.py when the container launches")
-        .cmd(r#"["python", "app.py"]"#);
-
-    let result = dock_generator.generate();
+    let provenance = Provenance::default()
+        .param("python_version", py_version);
+    let result = dockerfile(py_version)
+        .provenance(provenance)
+        .path(docker_file_path)
+        .generate();
 
     match result {
-        Ok(_) => println!("Docker file generated successfully"),
+        Ok(_) => {
+            println!("Docker file generated successfully");
+            print!("{}", dockerfile(py_version).build().compare_bases(&CANDIDATES, &SizeTable::default()));
+        },
         Err(error) => {
             match error {
                 GenerateError::InvalidArgument(reason) => println!("Failed to generated docker file: {}", reason),
                 GenerateError::IO(io_error) => println!("Failed to generated docker file: {}", io_error),
+                GenerateError::Incompatible(incompatibilities) => println!("Failed to generated docker file: {}", incompatibilities),
+                GenerateError::Denied(audit) => println!("Failed to generated docker file: {}", audit),
             }
         }
     }
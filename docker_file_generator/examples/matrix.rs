@@ -0,0 +1,47 @@
+use std::path::{ PathBuf };
+use std::env;
+
+use dock_gen::builder::DockerfileBuilder;
+use dock_gen::generator::DockerfileGenerator;
+use dock_gen::matrix::{Combination, Matrix};
+
+pub fn dockerfile(combination : &Combination) -> DockerfileBuilder {
+    let alpine = combination.value("variant") == "alpine";
+
+    DockerfileGenerator::builder()
+        .comment("Use an official Python runtime as a parent image")
+        .from(&format!("python:{}-{}", combination.value("python"), combination.value("variant")))
+        .empty_line()
+        .work_dir("/app")
+        .copy(".", "/app")
+        .empty_line()
+        .when(alpine, |b| b.comment("musl has no wheels for most packages, so they are built from source")
+                           .run("apk add --no-cache build-base"))
+        .run("pip install -r requirements.txt")
+        .empty_line()
+        .expose(80)
+        .cmd(r#"["python", "app.py"]"#)
+}
+
+fn main() {
+    // The Dockerfiles are written to the given directory, or to the temporary directory
+    let out_dir = match env::args().nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir(),
+    };
+
+    let mut matrix = Matrix::default();
+    matrix.axis("python", &["2.7", "3.7", "3.11"])
+        .axis("variant", &["slim", "alpine"]);
+
+    let template = out_dir.join("Dockerfile.{python}-{variant}");
+    let result = matrix.generate(template.to_str().unwrap(), dockerfile)
+        .and_then(|manifest| manifest.write(&out_dir.join("manifest.json")).map(|_| manifest));
+
+    match result {
+        Ok(manifest) => for output in &manifest.outputs {
+            println!("Generated {}", output.path.display());
+        },
+        Err(error) => println!("Failed to generate the docker files: {}", error),
+    }
+}
@@ -0,0 +1,21 @@
+use std::path::{ Path };
+use std::env;
+
+use dock_gen::generator::{DockerfileGenerator, GenerateError};
+use dock_gen::rust_project::{Options, Workspace};
+
+// A Dockerfile for a binary package of this repository's own workspace
+pub fn dockerfile(package : &str) -> Result<DockerfileGenerator, GenerateError> {
+    let workspace = Workspace::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join(".."))?;
+    workspace.dockerfile(package, &Options::default())
+}
+
+fn main() {
+    let package = env::args().nth(1).unwrap_or_else(|| String::from("overload_test"));
+
+    match dockerfile(&package) {
+        Ok(generator) => print!("{}", generator),
+        Err(GenerateError::InvalidArgument(reason)) => println!("Failed to generate docker file: {}", reason),
+        Err(error) => println!("Failed to generate docker file: {}", error),
+    }
+}
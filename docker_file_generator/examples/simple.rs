@@ -1,20 +1,12 @@
 use std::path::{ PathBuf };
 use std::fs;
 
+use dock_gen::builder::DockerfileBuilder;
 use dock_gen::generator::{DockerfileGenerator, GenerateError};
+use dock_gen::health::HealthProbe;
 
-fn main() {
-    let project_dir = env!("CARGO_MANIFEST_DIR");
-    let docker_file_path : PathBuf = [project_dir, "examples", "test_reference", "Dockerfile"].iter().collect();
-    println!("{}", docker_file_path.to_str().unwrap());
-
-    match fs::remove_file(docker_file_path.as_path()) {
-        Err(why) => println!("Failed to delete file {} : {:?}", docker_file_path.to_str().unwrap(), why.kind()),
-        Ok(_) => println!("File {} erased", docker_file_path.to_str().unwrap()),
-    }
-
-    let result = DockerfileGenerator::default()
-        .path(docker_file_path)
+pub fn dockerfile() -> DockerfileBuilder {
+    DockerfileGenerator::builder()
         .comment("Use an official Python runtime as a parent image")
         .from("python:2.7-slim")
         .empty_line()
@@ -35,6 +27,21 @@ fn main() {
         .empty_line()
         .comment("Run app.py when the container launches")
         .cmd(r#"["python", "app.py"]"#)
+        .health_probe(HealthProbe::http("/", 80))
+}
+
+fn main() {
+    let project_dir = env!("CARGO_MANIFEST_DIR");
+    let docker_file_path : PathBuf = [project_dir, "examples", "test_reference", "Dockerfile"].iter().collect();
+    println!("{}", docker_file_path.to_str().unwrap());
+
+    match fs::remove_file(docker_file_path.as_path()) {
+        Err(why) => println!("Failed to delete file {} : {:?}", docker_file_path.to_str().unwrap(), why.kind()),
+        Ok(_) => println!("File {} erased", docker_file_path.to_str().unwrap()),
+    }
+
+    let result = dockerfile()
+        .path(docker_file_path)
         .generate();
 
     match result {
@@ -43,7 +50,9 @@ fn main() {
             match error {
                 GenerateError::InvalidArgument(reason) => println! ("Failed to generated docker file: {}", reason),
                 GenerateError::IO(io_error) => println! ("Failed to generated docker file: {}", io_error),
+                GenerateError::Incompatible(incompatibilities) => println! ("Failed to generated docker file: {}", incompatibilities),
+                GenerateError::Denied(audit) => println! ("Failed to generated docker file: {}", audit),
             }
         }
     }
-}
\ No newline at end of file
+}
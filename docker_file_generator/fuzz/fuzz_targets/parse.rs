@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The parser must never panic, whatever it is given, nor on the rendering of what it accepts.
+// Degenerate input such as `RUN \\` or `VOLUME []` does not render to a Dockerfile parsing the same, so that is not checked.
+fuzz_target!(|data : &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        if let Ok(instructions) = dock_gen::parser::parse(source) {
+            let rendered : String = instructions.iter().map(|instruction| format!("{}\n", instruction)).collect();
+            let _ = dock_gen::parser::parse(&rendered);
+        }
+    }
+});
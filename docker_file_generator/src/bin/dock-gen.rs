@@ -0,0 +1,341 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use dock_gen::{codegen, diff, formatter, layers, parser, rust_project, security, watch};
+use dock_gen::policy::ImagePolicy;
+use dock_gen::provenance::{self, Verification};
+use dock_gen::size::{self, SizeTable};
+use dock_gen::instruction::Instruction;
+
+fn usage() -> ! {
+    println!("usage: dock-gen fmt [--check] <Dockerfile>...");
+    println!("       dock-gen diff [--json] <old Dockerfile> <new Dockerfile>");
+    println!("       dock-gen scan [--sarif] <Dockerfile>");
+    println!("       dock-gen policy [--json] <policy.toml> <Dockerfile>");
+    println!("       dock-gen cache [--json] <Dockerfile> <context> <changed file>...");
+    println!("       dock-gen rust <Dockerfile> [function]");
+    println!("       dock-gen verify <Dockerfile>...");
+    println!("       dock-gen watch <workspace> <package> [Dockerfile]");
+    println!("       dock-gen size [--json] [--table <sizes.toml>] <Dockerfile> [base image]...");
+    process::exit(2)
+}
+
+fn read(path : &str) -> Result<Vec<Instruction>, String> {
+    read_with_lines(path).map(|parsed| parsed.into_iter().map(|(_, instruction)| instruction).collect())
+}
+
+// The instructions with the lines they start at, to report them as written
+fn read_with_lines(path : &str) -> Result<Vec<(usize, Instruction)>, String> {
+    let source = fs::read_to_string(path).map_err(|error| format!("Failed to read {}: {}", path, error))?;
+    parser::parse_with_lines(&source).map_err(|error| format!("Failed to parse {}: {}", path, error))
+}
+
+fn fmt(args : &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths : Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        usage();
+    }
+
+    let mut status = 0;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                println!("Failed to read {}: {}", path, error);
+                status = 1;
+                continue;
+            },
+        };
+
+        let formatted = match formatter::format(&source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                println!("Failed to parse {}: {}", path, error);
+                status = 1;
+                continue;
+            },
+        };
+
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", path);
+            status = 1;
+        } else if let Err(error) = fs::write(path, formatted) {
+            println!("Failed to write {}: {}", path, error);
+            status = 1;
+        }
+    }
+    status
+}
+
+fn diff(args : &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths : Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    if paths.len() != 2 {
+        usage();
+    }
+
+    let (old, new) = match (read(paths[0]), read(paths[1])) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(error), _) | (_, Err(error)) => {
+            println!("{}", error);
+            return 1;
+        },
+    };
+
+    let diff = diff::diff(&old, &new);
+    if json {
+        println!("{}", diff.to_json());
+    } else {
+        print!("{}", diff);
+    }
+    0
+}
+
+fn scan(args : &[String]) -> i32 {
+    let sarif = args.iter().any(|arg| arg == "--sarif");
+    let paths : Vec<&String> = args.iter().filter(|arg| *arg != "--sarif").collect();
+    if paths.len() != 1 {
+        usage();
+    }
+
+    let findings = match fs::read_to_string(paths[0]) {
+        Ok(source) => match security::scan_source(&source) {
+            Ok(findings) => findings,
+            Err(error) => {
+                println!("Failed to parse {}: {}", paths[0], error);
+                return 1;
+            },
+        },
+        Err(error) => {
+            println!("Failed to read {}: {}", paths[0], error);
+            return 1;
+        },
+    };
+
+    if sarif {
+        println!("{}", security::to_sarif(&findings, paths[0]));
+    } else {
+        for finding in &findings {
+            println!("{}: {}", paths[0], finding);
+        }
+    }
+    if findings.is_empty() { 0 } else { 1 }
+}
+
+// Print the Dockerfile with its images rewritten, and the audit on stderr
+fn policy(args : &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths : Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    if paths.len() != 2 {
+        usage();
+    }
+
+    let (policy, source) = match (ImagePolicy::load(Path::new(paths[0])), fs::read_to_string(paths[1])) {
+        (Ok(policy), Ok(source)) => (policy, source),
+        (Err(error), _) => {
+            println!("Failed to load {}: {}", paths[0], error);
+            return 1;
+        },
+        (_, Err(error)) => {
+            println!("Failed to read {}: {}", paths[1], error);
+            return 1;
+        },
+    };
+
+    let (rewritten, audit) = match policy.apply_source(&source) {
+        Ok(applied) => applied,
+        Err(error) => {
+            println!("Failed to parse {}: {}", paths[1], error);
+            return 1;
+        },
+    };
+    for instruction in &rewritten {
+        println!("{}", instruction);
+    }
+    if json {
+        eprintln!("{}", audit.to_json());
+    } else {
+        eprint!("{}", audit);
+    }
+    if audit.is_denied() { 1 } else { 0 }
+}
+
+// Which layers a change to the context invalidates, e.g. `dock-gen cache Dockerfile . $(git diff --name-only)`
+fn cache(args : &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args : Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    if args.len() < 2 {
+        usage();
+    }
+
+    let parsed = match read_with_lines(args[0]) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            println!("{}", error);
+            return 1;
+        },
+    };
+    let changed : Vec<&str> = args[2..].iter().map(|path| path.as_str()).collect();
+    match layers::analyze_with_lines(&parsed, Path::new(args[1]), &changed) {
+        Ok(report) if json => println!("{}", report.to_json()),
+        Ok(report) => print!("{}", report),
+        Err(error) => {
+            println!("Failed to read the context {}: {}", args[1], error);
+            return 1;
+        },
+    }
+    0
+}
+
+// The Rust source building the Dockerfile with the fluent API
+fn rust(args : &[String]) -> i32 {
+    if args.is_empty() || args.len() > 2 {
+        usage();
+    }
+
+    match read(&args[0]) {
+        Ok(instructions) => {
+            print!("{}", codegen::to_rust(&instructions, args.get(1).map(String::as_str).unwrap_or("dockerfile")));
+            0
+        },
+        Err(error) => {
+            println!("{}", error);
+            1
+        },
+    }
+}
+
+// Whether generated Dockerfiles were edited since their provenance was recorded
+fn verify(args : &[String]) -> i32 {
+    if args.is_empty() {
+        usage();
+    }
+
+    let mut status = 0;
+    for path in args {
+        let verification = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path, error))
+            .and_then(|source| provenance::verify_provenance(&source).map_err(|error| format!("Failed to parse {}: {}", path, error)));
+        match verification {
+            Ok(Verification::Verified) => {},
+            Ok(Verification::Modified { recorded, actual }) => {
+                println!("{} was modified: recorded {}, now {}", path, recorded, actual);
+                status = 1;
+            },
+            Ok(Verification::Unrecorded) => {
+                println!("{} has no recorded spec hash", path);
+                status = 1;
+            },
+            Err(error) => {
+                println!("{}", error);
+                status = 1;
+            },
+        }
+    }
+    status
+}
+
+// Keep the Dockerfile and .dockerignore of a package of a Rust workspace up to date while its manifests and sources change
+fn watch(args : &[String]) -> i32 {
+    if args.len() < 2 || args.len() > 3 {
+        usage();
+    }
+
+    let root = Path::new(&args[0]);
+    let package = &args[1];
+    let output = args.get(2).map(PathBuf::from).unwrap_or_else(|| root.join("Dockerfile"));
+    let generate = || {
+        let mut generator = rust_project::Workspace::load(root)?.dockerfile(package, &rust_project::Options::default())?;
+        generator.path(output.clone());
+        Ok(generator)
+    };
+    let report = |changed : &[PathBuf], outcome : watch::Outcome| {
+        let cause = match changed {
+            [] => String::new(),
+            [path] => format!(" after {} changed", path.display()),
+            paths => format!(" after {} files changed", paths.len()),
+        };
+        match outcome {
+            watch::Outcome::Written(written) => {
+                for path in written {
+                    println!("wrote {}{}", path.display(), cause);
+                }
+            },
+            watch::Outcome::Unchanged => println!("{} is up to date{}", output.display(), cause),
+            watch::Outcome::Failed(error) => println!("failed to generate {}{}: {:?}", output.display(), cause, error),
+        }
+        true
+    };
+
+    match watch::watch(&[root], watch::Options::default(), generate, report) {
+        Ok(()) => 0,
+        Err(error) => {
+            println!("Failed to watch {}: {:?}", root.display(), error);
+            1
+        },
+    }
+}
+
+// The estimated size of the image, compared to the one on other base images
+fn size(args : &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let mut args : Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    let table = match args.iter().position(|arg| *arg == "--table") {
+        Some(index) if index + 1 < args.len() => {
+            let path = args.remove(index + 1);
+            args.remove(index);
+            match SizeTable::load(Path::new(path)) {
+                Ok(table) => table,
+                Err(error) => {
+                    println!("Failed to load {}: {:?}", path, error);
+                    return 1;
+                },
+            }
+        },
+        Some(_) => usage(),
+        None => SizeTable::default(),
+    };
+    if args.is_empty() {
+        usage();
+    }
+
+    let parsed = match read_with_lines(args[0]) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            println!("{}", error);
+            return 1;
+        },
+    };
+    let candidates : Vec<&str> = args[1..].iter().map(|image| image.as_str()).collect();
+    let comparison = size::compare_with_lines(&parsed, &candidates, &table);
+    if json {
+        println!("{}", comparison.to_json());
+    } else {
+        print!("{}", comparison);
+    }
+    0
+}
+
+fn main() {
+    let args : Vec<String> = env::args().skip(1).collect();
+    let status = match args.first().map(String::as_str) {
+        Some("fmt") => fmt(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some("scan") => scan(&args[1..]),
+        Some("policy") => policy(&args[1..]),
+        Some("cache") => cache(&args[1..]),
+        Some("rust") => rust(&args[1..]),
+        Some("verify") => verify(&args[1..]),
+        Some("watch") => watch(&args[1..]),
+        Some("size") => size(&args[1..]),
+        _ => usage(),
+    };
+    process::exit(status)
+}
@@ -0,0 +1,119 @@
+use std::path::{ PathBuf };
+
+use crate::compat::{Policy, Target};
+use crate::generator::{DockerfileGenerator, GenerateError};
+use crate::health::HealthProbe;
+use crate::instruction::Instruction;
+use crate::packages::PackageManager;
+use crate::policy::ImagePolicy;
+use crate::provenance::Provenance;
+use crate::visit::Transform;
+
+// Forward a generator instruction as a by-value method, so the whole Dockerfile can be built in a single expression
+macro_rules! forward {
+    ($($name:ident($($arg:ident : $arg_type:ty),*);)*) => {
+        $(
+            pub fn $name(mut self, $($arg : $arg_type),*) -> DockerfileBuilder {
+                self.generator.$name($($arg),*);
+                self
+            }
+        )*
+    };
+}
+
+// Owned counterpart of DockerfileGenerator: every method takes self by value and returns it
+#[derive(Default)]
+pub struct DockerfileBuilder {
+    generator : DockerfileGenerator,
+}
+
+impl DockerfileBuilder {
+    forward! {
+        path(path : PathBuf);
+        ignore(pattern : &str);
+        target(target : Target, policy : Policy);
+        image_policy(policy : ImagePolicy);
+        provenance(provenance : Provenance);
+        health_probe(probe : HealthProbe);
+        comment(line : &str);
+        from(line : &str);
+        work_dir(line : &str);
+        copy(from : &str, to : &str);
+        run(line : &str);
+        run_script(script : &str);
+        copy_content(content : &str, to : &str);
+        install(packages : &[&str]);
+        install_with(manager : PackageManager, packages : &[&str]);
+        install_build_deps(build : &[&str], command : &str);
+        run_as_user(name : &str, uid : u32, gid : u32);
+        expose(port : u32);
+        env(key : &str, value : &str);
+        cmd(line : &str);
+        entrypoint(line : &str);
+        add(from : &str, to : &str);
+        user(line : &str);
+        label(key : &str, value : &str);
+        volume(line : &str);
+        arg(line : &str);
+        stop_signal(signal : &str);
+        healthcheck(line : &str);
+        empty_line();
+        push(line : &str);
+        push_instruction(instruction : Instruction);
+    }
+
+    pub fn when<F>(self, condition : bool, f : F) -> DockerfileBuilder
+        where F: FnOnce(DockerfileBuilder) -> DockerfileBuilder {
+        if condition {
+            f(self)
+        } else {
+            self
+        }
+    }
+
+    pub fn when_else<F, G>(self, condition : bool, then : F, otherwise : G) -> DockerfileBuilder
+        where F: FnOnce(DockerfileBuilder) -> DockerfileBuilder,
+              G: FnOnce(DockerfileBuilder) -> DockerfileBuilder {
+        if condition {
+            then(self)
+        } else {
+            otherwise(self)
+        }
+    }
+
+    pub fn for_each<I, F>(self, items : I, f : F) -> DockerfileBuilder
+        where I: IntoIterator,
+              F: FnMut(DockerfileBuilder, I::Item) -> DockerfileBuilder {
+        items.into_iter().fold(self, f)
+    }
+
+    pub fn apply<F>(self, f : F) -> DockerfileBuilder
+        where F: FnOnce(DockerfileBuilder) -> DockerfileBuilder {
+        f(self)
+    }
+
+    pub fn transform<T : Transform + 'static>(mut self, pass : T) -> DockerfileBuilder {
+        self.generator.transform(pass);
+        self
+    }
+
+    pub fn build(self) -> DockerfileGenerator {
+        self.generator
+    }
+
+    pub fn generate(mut self) -> Result<(), GenerateError> {
+        self.generator.generate()
+    }
+}
+
+impl From<DockerfileGenerator> for DockerfileBuilder {
+    fn from(generator : DockerfileGenerator) -> DockerfileBuilder {
+        DockerfileBuilder { generator }
+    }
+}
+
+impl From<DockerfileBuilder> for DockerfileGenerator {
+    fn from(builder : DockerfileBuilder) -> DockerfileGenerator {
+        builder.generator
+    }
+}
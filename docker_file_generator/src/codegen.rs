@@ -0,0 +1,237 @@
+use crate::generator::DockerfileGenerator;
+use crate::instruction::{Command, Flag, Heredoc, Instruction};
+
+enum Arg {
+    Str(String),
+    Port(u32),
+}
+
+// A call of the fluent API, such as `.copy(".", "/app")`
+struct Call {
+    method : &'static str,
+    args   : Vec<Arg>,
+}
+
+// Rust source of a function building the instructions with the fluent API, in the style of examples/simple.rs.
+// Every call is checked to produce the very instruction it stands for.
+pub fn to_rust(instructions : &[Instruction], function : &str) -> String {
+    let mut literals = false;
+    let mut calls = Vec::new();
+    for instruction in instructions {
+        match call(instruction) {
+            Some(call) => calls.push(render(&call)),
+            None => {
+                literals = true;
+                calls.push(format!(".push_instruction({})", literal(instruction)));
+            },
+        }
+    }
+
+    let mut source = String::new();
+    source.push_str("use dock_gen::builder::DockerfileBuilder;\n");
+    source.push_str("use dock_gen::generator::DockerfileGenerator;\n");
+    if literals {
+        source.push_str("#[allow(unused_imports)]\n");
+        source.push_str("use dock_gen::instruction::{Command, Flag, Heredoc, Instruction};\n");
+    }
+    source.push('\n');
+    source.push_str(&format!("pub fn {}() -> DockerfileBuilder {{\n", function));
+    source.push_str("    DockerfileGenerator::builder()");
+    for call in &calls {
+        source.push_str("\n        ");
+        source.push_str(call);
+    }
+    source.push_str("\n}\n");
+    source
+}
+
+// The most readable call producing the instruction, or None when only the instruction itself will do
+fn call(instruction : &Instruction) -> Option<Call> {
+    let rendered = instruction.to_string();
+    let rest = |keyword : &str| rendered[keyword.len()..].trim_start().to_string();
+    let single = |method : &'static str, keyword : &str| Call { method, args: vec![Arg::Str(rest(keyword))] };
+
+    let candidate = match instruction {
+        Instruction::Empty => Call { method: "empty_line", args: Vec::new() },
+        Instruction::Comment(text) => Call { method: "comment", args: vec![Arg::Str(text.clone())] },
+        Instruction::From { .. } => single("from", "FROM"),
+        Instruction::WorkDir(_) => single("work_dir", "WORKDIR"),
+        Instruction::Cmd(_) => single("cmd", "CMD"),
+        Instruction::Entrypoint(_) => single("entrypoint", "ENTRYPOINT"),
+        Instruction::User(_) => single("user", "USER"),
+        Instruction::Volume(_) => single("volume", "VOLUME"),
+        Instruction::Arg { .. } => single("arg", "ARG"),
+        Instruction::StopSignal(_) => single("stop_signal", "STOPSIGNAL"),
+        Instruction::Healthcheck { .. } => single("healthcheck", "HEALTHCHECK"),
+        Instruction::Run { flags, command: Command::Shell(marker), heredocs } if flags.is_empty() && heredocs.len() == 1 && *marker == heredocs[0].marker() =>
+            Call { method: "run_script", args: vec![Arg::Str(heredocs[0].body.clone())] },
+        Instruction::Run { heredocs, .. } if heredocs.is_empty() => single("run", "RUN"),
+        Instruction::Copy { flags, sources, destination, heredocs } if flags.is_empty() && heredocs.len() == 1 && *sources == [heredocs[0].marker()] =>
+            Call { method: "copy_content", args: vec![Arg::Str(heredocs[0].body.clone()), Arg::Str(destination.clone())] },
+        Instruction::Copy { flags, sources, destination, heredocs } | Instruction::Add { flags, sources, destination, heredocs } if heredocs.is_empty() => {
+            let method = if matches!(instruction, Instruction::Copy { .. }) { "copy" } else { "add" };
+            let mut from : Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+            from.extend(sources.iter().cloned());
+            Call { method, args: vec![Arg::Str(from.join(" ")), Arg::Str(destination.clone())] }
+        },
+        Instruction::Env(pairs) if pairs.len() == 1 =>
+            Call { method: "env", args: vec![Arg::Str(pairs[0].0.clone()), Arg::Str(crate::instruction::quote(&pairs[0].1))] },
+        Instruction::Label(pairs) if pairs.len() == 1 =>
+            Call { method: "label", args: vec![Arg::Str(pairs[0].0.clone()), Arg::Str(pairs[0].1.clone())] },
+        Instruction::Expose(ports) if ports.len() == 1 && ports[0].parse::<u32>().is_ok() =>
+            Call { method: "expose", args: vec![Arg::Port(ports[0].parse().unwrap())] },
+        _ => Call { method: "push", args: vec![Arg::Str(rendered.clone())] },
+    };
+
+    if reproduces(&candidate, instruction) {
+        return Some(candidate);
+    }
+    let push = Call { method: "push", args: vec![Arg::Str(rendered)] };
+    if reproduces(&push, instruction) { Some(push) } else { None }
+}
+
+fn reproduces(call : &Call, instruction : &Instruction) -> bool {
+    let mut generator = DockerfileGenerator::default();
+    let str = |index : usize| match call.args.get(index) {
+        Some(Arg::Str(value)) => value.as_str(),
+        _ => "",
+    };
+    match call.method {
+        "empty_line" => generator.empty_line(),
+        "comment" => generator.comment(str(0)),
+        "from" => generator.from(str(0)),
+        "work_dir" => generator.work_dir(str(0)),
+        "cmd" => generator.cmd(str(0)),
+        "entrypoint" => generator.entrypoint(str(0)),
+        "user" => generator.user(str(0)),
+        "volume" => generator.volume(str(0)),
+        "arg" => generator.arg(str(0)),
+        "stop_signal" => generator.stop_signal(str(0)),
+        "healthcheck" => generator.healthcheck(str(0)),
+        "run" => generator.run(str(0)),
+        "run_script" => generator.run_script(str(0)),
+        "copy" => generator.copy(str(0), str(1)),
+        "add" => generator.add(str(0), str(1)),
+        "copy_content" => generator.copy_content(str(0), str(1)),
+        "env" => generator.env(str(0), str(1)),
+        "label" => generator.label(str(0), str(1)),
+        "expose" => match call.args.first() {
+            Some(Arg::Port(port)) => generator.expose(*port),
+            _ => return false,
+        },
+        _ => generator.push(str(0)),
+    };
+    generator.instructions() == [instruction.clone()]
+}
+
+fn render(call : &Call) -> String {
+    let args : Vec<String> = call.args.iter().map(|arg| match arg {
+        Arg::Str(value) => string(value),
+        Arg::Port(port) => port.to_string(),
+    }).collect();
+    format!(".{}({})", call.method, args.join(", "))
+}
+
+// A string literal, raw when it saves escaping quotes and backslashes
+fn string(value : &str) -> String {
+    let escaped = value.contains(['"', '\\']);
+    if escaped && !value.contains("\"#") && !value.chars().any(|c| c.is_control()) {
+        format!("r#\"{}\"#", value)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn strings(values : &[String]) -> String {
+    let values : Vec<String> = values.iter().map(|value| format!("String::from({})", string(value))).collect();
+    format!("vec![{}]", values.join(", "))
+}
+
+fn pairs(pairs : &[(String, String)]) -> String {
+    let pairs : Vec<String> = pairs.iter().map(|(key, value)| format!("(String::from({}), String::from({}))", string(key), string(value))).collect();
+    format!("vec![{}]", pairs.join(", "))
+}
+
+fn option(value : &Option<String>) -> String {
+    match value {
+        Some(value) => format!("Some(String::from({}))", string(value)),
+        None => String::from("None"),
+    }
+}
+
+fn flags(flags : &[Flag]) -> String {
+    let flags : Vec<String> = flags.iter().map(|flag| format!("Flag {{ name: String::from({}), value: {} }}", string(&flag.name), option(&flag.value))).collect();
+    format!("vec![{}]", flags.join(", "))
+}
+
+fn command(command : &Command) -> String {
+    match command {
+        Command::Shell(line) => format!("Command::Shell(String::from({}))", string(line)),
+        Command::Exec(args) => format!("Command::Exec({})", strings(args)),
+    }
+}
+
+fn heredocs(heredocs : &[Heredoc]) -> String {
+    let heredocs : Vec<String> = heredocs.iter()
+        .map(|heredoc| format!("Heredoc {{ delimiter: String::from({}), body: String::from({}) }}", string(&heredoc.delimiter), string(&heredoc.body)))
+        .collect();
+    format!("vec![{}]", heredocs.join(", "))
+}
+
+// The instruction as a Rust expression
+fn literal(instruction : &Instruction) -> String {
+    match instruction {
+        Instruction::Empty => String::from("Instruction::Empty"),
+        Instruction::Comment(text) => format!("Instruction::Comment(String::from({}))", string(text)),
+        Instruction::From { flags: from_flags, image, alias } =>
+            format!("Instruction::From {{ flags: {}, image: String::from({}), alias: {} }}", flags(from_flags), string(image), option(alias)),
+        Instruction::Run { flags: run_flags, command: run_command, heredocs: run_heredocs } =>
+            format!("Instruction::Run {{ flags: {}, command: {}, heredocs: {} }}", flags(run_flags), command(run_command), heredocs(run_heredocs)),
+        Instruction::Cmd(cmd) => format!("Instruction::Cmd({})", command(cmd)),
+        Instruction::Entrypoint(entrypoint) => format!("Instruction::Entrypoint({})", command(entrypoint)),
+        Instruction::Label(labels) => format!("Instruction::Label({})", pairs(labels)),
+        Instruction::Maintainer(name) => format!("Instruction::Maintainer(String::from({}))", string(name)),
+        Instruction::Expose(ports) => format!("Instruction::Expose({})", strings(ports)),
+        Instruction::Env(variables) => format!("Instruction::Env({})", pairs(variables)),
+        Instruction::Add { flags: add_flags, sources, destination, heredocs: add_heredocs } =>
+            format!("Instruction::Add {{ flags: {}, sources: {}, destination: String::from({}), heredocs: {} }}",
+                    flags(add_flags), strings(sources), string(destination), heredocs(add_heredocs)),
+        Instruction::Copy { flags: copy_flags, sources, destination, heredocs: copy_heredocs } =>
+            format!("Instruction::Copy {{ flags: {}, sources: {}, destination: String::from({}), heredocs: {} }}",
+                    flags(copy_flags), strings(sources), string(destination), heredocs(copy_heredocs)),
+        Instruction::Volume(volumes) => format!("Instruction::Volume({})", strings(volumes)),
+        Instruction::User(user) => format!("Instruction::User(String::from({}))", string(user)),
+        Instruction::WorkDir(dir) => format!("Instruction::WorkDir(String::from({}))", string(dir)),
+        Instruction::Arg { name, default } => format!("Instruction::Arg {{ name: String::from({}), default: {} }}", string(name), option(default)),
+        Instruction::OnBuild(trigger) => format!("Instruction::OnBuild(Box::new({}))", literal(trigger)),
+        Instruction::StopSignal(signal) => format!("Instruction::StopSignal(String::from({}))", string(signal)),
+        Instruction::Healthcheck { flags: check_flags, command: check } => {
+            let check = match check {
+                Some(check) => format!("Some({})", command(check)),
+                None => String::from("None"),
+            };
+            format!("Instruction::Healthcheck {{ flags: {}, command: {} }}", flags(check_flags), check)
+        },
+        Instruction::Shell(shell) => format!("Instruction::Shell({})", strings(shell)),
+        Instruction::Raw(line) => format!("Instruction::Raw(String::from({}))", string(line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn prefers_typed_calls() {
+        let instructions = parse("# base\nFROM python:3.7-slim\n\nENV GREETING \"hello world\"\nEXPOSE 80\nLABEL a=1 b=2\nCOPY [\"a b\", \"/app/\"]\n").unwrap();
+        let source = to_rust(&instructions, "dockerfile");
+
+        assert!(source.contains("\n        .comment(\"base\")\n        .from(\"python:3.7-slim\")\n        .empty_line()\n"));
+        assert!(source.contains(".env(\"GREETING\", r#\"\"hello world\"\"#)"));
+        assert!(source.contains(".expose(80)"));
+        assert!(source.contains(".push(\"LABEL a=1 b=2\")"));
+        assert!(source.contains(r##".push(r#"COPY ["a b", "/app/"]"#)"##));
+        assert!(!source.contains("push_instruction"));
+    }
+}
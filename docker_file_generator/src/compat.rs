@@ -0,0 +1,345 @@
+use std::fmt;
+
+use crate::instruction::{self, Command, Flag, Instruction};
+use crate::parser::{self, ParseError};
+
+// The tool which is going to build the generated file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    // `docker build` with DOCKER_BUILDKIT=0
+    LegacyDocker,
+    BuildKit,
+    // Podman and Buildah, which build OCI images by default
+    Podman,
+    Kaniko,
+}
+
+// What to do with instructions the target does not understand
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    Reject,
+    // Rewrite them into an equivalent the target supports, when there is one
+    Downgrade,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    Heredoc,
+    RunMount,
+    RunNetwork,
+    RunSecurity,
+    CopyChmod,
+    CopyLink,
+    AddChecksum,
+    AddGit,
+    // HEALTHCHECK, SHELL and ONBUILD only exist in the Docker image format
+    DockerFormat,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Incompatibility {
+    pub feature     : Feature,
+    // Index of the instruction, its line in the Dockerfile, and the instruction as written
+    pub index       : usize,
+    pub line        : usize,
+    pub instruction : String,
+    // How the instruction is rewritten under Policy::Downgrade, if it can be
+    pub downgrade   : Option<String>,
+}
+
+// Everything that prevents a Dockerfile from being built by a target
+#[derive(Clone, Debug, PartialEq)]
+pub struct Incompatibilities {
+    pub target : Target,
+    pub items  : Vec<Incompatibility>,
+}
+
+impl Target {
+    // The file name the target looks for by default
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Target::Podman => "Containerfile",
+            _ => "Dockerfile",
+        }
+    }
+
+    pub fn supports(self, feature : Feature) -> bool {
+        match feature {
+            Feature::Heredoc | Feature::RunMount | Feature::RunNetwork => self == Target::BuildKit || self == Target::Podman,
+            Feature::CopyChmod => self != Target::LegacyDocker,
+            Feature::RunSecurity | Feature::CopyLink | Feature::AddChecksum | Feature::AddGit => self == Target::BuildKit,
+            Feature::DockerFormat => self != Target::Podman,
+        }
+    }
+}
+
+impl Feature {
+    pub fn description(self) -> &'static str {
+        match self {
+            Feature::Heredoc => "heredocs",
+            Feature::RunMount => "RUN --mount",
+            Feature::RunNetwork => "RUN --network",
+            Feature::RunSecurity => "RUN --security",
+            Feature::CopyChmod => "COPY --chmod",
+            Feature::CopyLink => "COPY --link",
+            Feature::AddChecksum => "ADD --checksum",
+            Feature::AddGit => "ADD of a git repository",
+            Feature::DockerFormat => "instructions of the Docker image format",
+        }
+    }
+}
+
+// Every feature used by the instructions which the target does not support
+pub fn check(instructions : &[Instruction], target : Target) -> Vec<Incompatibility> {
+    let lines = instruction::rendered_lines(instructions);
+    let mut incompatibilities = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        for feature in features(instruction) {
+            if !target.supports(feature) {
+                incompatibilities.push(Incompatibility {
+                    feature,
+                    index,
+                    line: lines[index],
+                    instruction: instruction.to_string(),
+                    downgrade: lower(instruction, feature).map(|(_, description)| description),
+                });
+            }
+        }
+    }
+    incompatibilities
+}
+
+// Check a Dockerfile's source, reporting the lines as written in it
+pub fn check_source(source : &str, target : Target) -> Result<Vec<Incompatibility>, ParseError> {
+    let parsed = parser::parse_with_lines(source)?;
+    let instructions : Vec<Instruction> = parsed.iter().map(|(_, instruction)| instruction.clone()).collect();
+
+    let mut incompatibilities = check(&instructions, target);
+    for incompatibility in &mut incompatibilities {
+        incompatibility.line = parsed[incompatibility.index].0;
+    }
+    Ok(incompatibilities)
+}
+
+// Rewrite the instructions for the target. Fails with the incompatibilities which have no downgrade.
+pub fn downgrade(instructions : &[Instruction], target : Target) -> Result<Vec<Instruction>, Incompatibilities> {
+    let blocking : Vec<Incompatibility> = check(instructions, target).into_iter()
+        .filter(|incompatibility| incompatibility.downgrade.is_none())
+        .collect();
+    if !blocking.is_empty() {
+        return Err(Incompatibilities { target, items: blocking });
+    }
+
+    let mut downgraded = Vec::new();
+    for instruction in instructions {
+        let mut current = vec![instruction.clone()];
+        for feature in features(instruction) {
+            if target.supports(feature) || current.is_empty() {
+                continue;
+            }
+            // Downgrades only touch the instruction itself, anything they add comes after it
+            let (mut rewritten, _) = lower(&current[0], feature).expect("blocking incompatibilities were rejected");
+            rewritten.extend(current.drain(1..));
+            current = rewritten;
+        }
+        downgraded.extend(current);
+    }
+    Ok(downgraded)
+}
+
+fn features(instruction : &Instruction) -> Vec<Feature> {
+    let mut features = Vec::new();
+    let has = |name : &str| instruction.flag(name).is_some();
+
+    match instruction {
+        Instruction::Run { command, .. } => {
+            let marker = matches!(command, Command::Shell(line) if line.trim_start().starts_with("<<"));
+            if marker || !instruction.heredocs().is_empty() {
+                features.push(Feature::Heredoc);
+            }
+            if has("mount") {
+                features.push(Feature::RunMount);
+            }
+            if has("network") {
+                features.push(Feature::RunNetwork);
+            }
+            if has("security") {
+                features.push(Feature::RunSecurity);
+            }
+        },
+        Instruction::Copy { sources, .. } | Instruction::Add { sources, .. } => {
+            if sources.iter().any(|source| source.starts_with("<<")) || !instruction.heredocs().is_empty() {
+                features.push(Feature::Heredoc);
+            }
+            if has("chmod") {
+                features.push(Feature::CopyChmod);
+            }
+            if has("link") {
+                features.push(Feature::CopyLink);
+            }
+            if has("checksum") {
+                features.push(Feature::AddChecksum);
+            }
+            let git = sources.iter().any(|source| source.starts_with("git@") || source.ends_with(".git") || source.contains(".git#"));
+            if git && matches!(instruction, Instruction::Add { .. }) {
+                features.push(Feature::AddGit);
+            }
+        },
+        Instruction::Healthcheck { .. } | Instruction::Shell(_) | Instruction::OnBuild(_) => features.push(Feature::DockerFormat),
+        _ => {},
+    }
+    features
+}
+
+// The instructions replacing one using the feature, and a description of the change
+fn lower(instruction : &Instruction, feature : Feature) -> Option<(Vec<Instruction>, String)> {
+    match feature {
+        // Cache and tmpfs mounts only speed the build up, anything else changes what the command sees
+        Feature::RunMount => {
+            let droppable = instruction.flags().iter()
+                .filter(|flag| flag.name == "mount")
+                .all(|flag| flag.value.as_ref().is_some_and(|value| value.contains("type=cache") || value.contains("type=tmpfs")));
+            if !droppable {
+                return None;
+            }
+            Some((vec![without_flag(instruction, "mount")], String::from("the cache mounts are dropped")))
+        },
+        Feature::RunNetwork | Feature::RunSecurity => {
+            let name = if feature == Feature::RunNetwork { "network" } else { "security" };
+            let default = if feature == Feature::RunNetwork { "default" } else { "sandbox" };
+            if instruction.flag(name)?.value.as_deref() != Some(default) {
+                return None;
+            }
+            Some((vec![without_flag(instruction, name)], format!("--{}={} is the default and is dropped", name, default)))
+        },
+        Feature::CopyLink => Some((vec![without_flag(instruction, "link")], String::from("--link is dropped"))),
+        // The mode is applied by a RUN chmod after the copy, which needs a shell and a user allowed to change the files
+        Feature::CopyChmod => {
+            let mode = instruction.flag("chmod")?.value.clone()?;
+            let (sources, destination) = match instruction {
+                Instruction::Copy { sources, destination, .. } | Instruction::Add { sources, destination, .. } => (sources, destination),
+                _ => return None,
+            };
+            let targets = copied_paths(sources, destination)?;
+            let mut args = vec![String::from("chmod"), mode];
+            args.extend(targets);
+            let chmod = Instruction::Run { flags: Vec::new(), command: Command::Shell(args.join(" ")), heredocs: Vec::new() };
+            Some((vec![without_flag(instruction, "chmod"), chmod], String::from("the mode is set by a RUN chmod after the copy")))
+        },
+        Feature::DockerFormat => Some((Vec::new(), String::from("the instruction is dropped, as the OCI format ignores it"))),
+        Feature::Heredoc | Feature::AddChecksum | Feature::AddGit => None,
+    }
+}
+
+fn without_flag(instruction : &Instruction, name : &str) -> Instruction {
+    let mut instruction = instruction.clone();
+    let keep = |flags : &mut Vec<Flag>| flags.retain(|flag| flag.name != name);
+    match &mut instruction {
+        Instruction::From { flags, .. } | Instruction::Run { flags, .. } | Instruction::Add { flags, .. }
+        | Instruction::Copy { flags, .. } | Instruction::Healthcheck { flags, .. } => keep(flags),
+        _ => {},
+    }
+    instruction
+}
+
+// The paths a copy creates, when they can be known without looking at the build context
+fn copied_paths(sources : &[String], destination : &str) -> Option<Vec<String>> {
+    if sources.iter().any(|source| source.contains(['*', '?', '[']) || source.contains("://")) {
+        return None;
+    }
+    if sources.len() == 1 && !destination.ends_with('/') {
+        return Some(vec![destination.to_string()]);
+    }
+    if !destination.ends_with('/') {
+        return None;
+    }
+
+    sources.iter().map(|source| {
+        let name = source.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty() && *name != "." && *name != "..")?;
+        Some(format!("{}{}", destination, name))
+    }).collect()
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Target::LegacyDocker => "Docker (legacy builder)",
+            Target::BuildKit => "BuildKit",
+            Target::Podman => "Podman/Buildah",
+            Target::Kaniko => "Kaniko",
+        })
+    }
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {} is not supported: {}", self.line, self.feature.description(), self.instruction)?;
+        if let Some(ref downgrade) = self.downgrade {
+            write!(f, " (downgrade: {})", downgrade)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Incompatibilities {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} incompatibilities with {}:", self.items.len(), self.target)?;
+        for item in &self.items {
+            write!(f, "\n  {}", item)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compat::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn lists_unsupported_features() {
+        let instructions = parse("FROM rust:1.70\n\
+                                  RUN --mount=type=cache,target=/usr/local/cargo/registry cargo build\n\
+                                  RUN --mount=type=secret,id=token ./publish.sh\n\
+                                  COPY --chmod=755 --link entrypoint.sh /usr/local/bin/\n\
+                                  HEALTHCHECK CMD curl -f http://localhost/\n").unwrap();
+
+        assert!(check(&instructions, Target::BuildKit).is_empty());
+
+        let legacy = check(&instructions, Target::LegacyDocker);
+        let features : Vec<Feature> = legacy.iter().map(|incompatibility| incompatibility.feature).collect();
+        assert_eq!(features, vec![Feature::RunMount, Feature::RunMount, Feature::CopyChmod, Feature::CopyLink]);
+        assert_eq!(legacy[1].line, 3);
+        assert_eq!(legacy[1].downgrade, None);
+
+        let podman = check(&instructions, Target::Podman);
+        assert_eq!(podman.iter().map(|incompatibility| incompatibility.feature).collect::<Vec<_>>(), vec![Feature::CopyLink, Feature::DockerFormat]);
+
+        let error = downgrade(&instructions, Target::LegacyDocker).unwrap_err();
+        assert_eq!(error.items.len(), 1);
+        assert!(error.to_string().starts_with("1 incompatibilities with Docker (legacy builder):\n  line 3: RUN --mount"));
+
+        let source = "FROM rust:1.70\n# build with a cache\nRUN --mount=type=cache,target=/root/.cargo \\\n    cargo build\nCOPY --link . /app\n";
+        let located : Vec<(usize, usize)> = check_source(source, Target::LegacyDocker).unwrap().iter().map(|item| (item.index, item.line)).collect();
+        assert_eq!(located, vec![(2, 3), (3, 5)]);
+
+        let instructions = parse("FROM rust:1.70\nRUN <<EOF\ncargo build\nEOF\nCOPY --link . /app\n").unwrap();
+        assert_eq!(check(&instructions, Target::LegacyDocker).iter().map(|item| (item.index, item.line)).collect::<Vec<_>>(), vec![(1, 2), (2, 5)]);
+    }
+
+    #[test]
+    fn downgrades_to_supported_instructions() {
+        let instructions = parse("FROM rust:1.70\n\
+                                  RUN --mount=type=cache,target=/root/.cargo cargo build\n\
+                                  COPY --chown=app --chmod=755 --link entrypoint.sh config.toml /app/\n").unwrap();
+
+        let downgraded = downgrade(&instructions, Target::LegacyDocker).unwrap();
+        let rendered : Vec<String> = downgraded.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(rendered, vec![
+            "FROM rust:1.70",
+            "RUN cargo build",
+            "COPY --chown=app entrypoint.sh config.toml /app/",
+            "RUN chmod 755 /app/entrypoint.sh /app/config.toml",
+        ]);
+    }
+}
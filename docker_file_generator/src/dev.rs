@@ -0,0 +1,284 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::generator::{DockerfileGenerator, GenerateError};
+use crate::instruction::{Command, Instruction};
+use crate::layers::MANIFESTS;
+use crate::packages::PackageManager;
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub name        : String,
+    // Packages installed on top of the final stage
+    pub tools       : Vec<String>,
+    // Ports of the debugger, e.g. 5678 for debugpy or 9229 for node --inspect
+    pub debug_ports : Vec<u32>,
+    // File name of the dev Dockerfile, next to the .devcontainer directory
+    pub dockerfile  : String,
+    // Where the sources are mounted when the final stage has no WORKDIR
+    pub workspace   : String,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            name: String::from("dev"),
+            tools: ["curl", "gdb", "git", "procps", "strace"].iter().map(|tool| tool.to_string()).collect(),
+            debug_ports: Vec::new(),
+            dockerfile: String::from("Dockerfile.dev"),
+            workspace: String::from("/workspace"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Build {
+    pub dockerfile : String,
+    pub context    : String,
+}
+
+// The subset of devcontainer.json the dev variant needs
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DevContainer {
+    pub name             : String,
+    pub build            : Build,
+    pub workspace_mount  : String,
+    pub workspace_folder : String,
+    pub forward_ports    : Vec<u32>,
+}
+
+impl DevContainer {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a devcontainer is always serializable")
+    }
+}
+
+// The final stage of the Dockerfile made into a development image: the debugging tools are installed,
+// the debugger ports exposed, and the sources are mounted in the WORKDIR instead of being copied.
+// The dependency manifests are still copied, on their own when the sources brought them in, so the steps
+// installing the dependencies still find them.
+pub fn variant(instructions : &[Instruction], options : &Options) -> Vec<Instruction> {
+    let last = match instructions.iter().rposition(|instruction| matches!(instruction, Instruction::From { .. })) {
+        Some(last) => last,
+        None => return instructions.to_vec(),
+    };
+    let (workspace, has_workdir) = workspace(&instructions[last..], options);
+
+    let mut dev = instructions[..=last].to_vec();
+    if !options.tools.is_empty() {
+        let install = PackageManager::for_stage(&dev).install(&options.tools);
+        dev.push(Instruction::Comment(String::from("Development tools")));
+        dev.push(Instruction::Run { flags: Vec::new(), command: Command::Shell(install), heredocs: Vec::new() });
+    }
+    if !options.debug_ports.is_empty() {
+        dev.push(Instruction::Expose(options.debug_ports.iter().map(|port| port.to_string()).collect()));
+    }
+    if !has_workdir {
+        dev.push(Instruction::WorkDir(workspace.clone()));
+    }
+
+    let mut mounted = false;
+    let mut manifests : Vec<String> = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate().skip(last + 1) {
+        if !is_source_copy(instruction) {
+            if let Instruction::Copy { sources, .. } | Instruction::Add { sources, .. } = instruction {
+                manifests.extend(sources.iter().map(|source| file_name(source).to_string()));
+            }
+            dev.push(instruction.clone());
+            continue;
+        }
+        for manifest in read_manifests(&instructions[index + 1..]) {
+            if !manifests.contains(&manifest) {
+                dev.push(Instruction::Copy { flags: Vec::new(), sources: vec![manifest.clone()], destination: format!("{}/", workspace.trim_end_matches('/')), heredocs: Vec::new() });
+                manifests.push(manifest);
+            }
+        }
+        if !mounted {
+            mounted = true;
+            dev.push(Instruction::Comment(String::from("The sources are mounted here by the dev container")));
+            dev.push(Instruction::Volume(vec![workspace.clone()]));
+        }
+    }
+    dev
+}
+
+// The devcontainer.json building the dev variant, which lives in .devcontainer next to it
+pub fn devcontainer(instructions : &[Instruction], options : &Options) -> DevContainer {
+    let start = instructions.iter().rposition(|instruction| matches!(instruction, Instruction::From { .. })).unwrap_or(0);
+    let (workspace, _) = workspace(&instructions[start..], options);
+
+    let mut ports = Vec::new();
+    for instruction in &instructions[start..] {
+        if let Instruction::Expose(exposed) = instruction {
+            for port in exposed.iter().flat_map(|port| expose_range(port)) {
+                if !ports.contains(&port) {
+                    ports.push(port);
+                }
+            }
+        }
+    }
+    for port in &options.debug_ports {
+        if !ports.contains(port) {
+            ports.push(*port);
+        }
+    }
+
+    DevContainer {
+        name: options.name.clone(),
+        build: Build { dockerfile: format!("../{}", options.dockerfile), context: String::from("..") },
+        workspace_mount: format!("source=${{localWorkspaceFolder}},target={},type=bind", workspace),
+        workspace_folder: workspace,
+        forward_ports: ports,
+    }
+}
+
+// Write the dev Dockerfile and .devcontainer/devcontainer.json in the directory
+pub fn write(generator : &DockerfileGenerator, dir : &Path, options : &Options) -> Result<(), GenerateError> {
+    let dev = generator.dev(options);
+    fs::write(dir.join(&options.dockerfile), dev.to_string()).map_err(GenerateError::IO)?;
+
+    let devcontainer_dir = dir.join(".devcontainer");
+    fs::create_dir_all(&devcontainer_dir).map_err(GenerateError::IO)?;
+    fs::write(devcontainer_dir.join("devcontainer.json"), devcontainer(dev.instructions(), options).to_json()).map_err(GenerateError::IO)
+}
+
+// The last WORKDIR of the stage, and whether there is one
+fn workspace(stage : &[Instruction], options : &Options) -> (String, bool) {
+    let workdir = stage.iter().rev().find_map(|instruction| match instruction {
+        Instruction::WorkDir(dir) if dir.starts_with('/') => Some(dir.clone()),
+        _ => None,
+    });
+    match workdir {
+        Some(dir) => (dir, true),
+        None => (options.workspace.clone(), false),
+    }
+}
+
+// A COPY or ADD of files from the build context, other than the dependency manifests
+fn is_source_copy(instruction : &Instruction) -> bool {
+    let (flags, sources, heredocs) = match instruction {
+        Instruction::Copy { flags, sources, heredocs, .. } => (flags, sources, heredocs),
+        Instruction::Add { flags, sources, heredocs, .. } => (flags, sources, heredocs),
+        _ => return false,
+    };
+    let from_stage = flags.iter().any(|flag| flag.name == "from");
+    let remote = sources.iter().all(|source| source.contains("://") || source.starts_with("git@"));
+    let manifests = sources.iter().all(|source| MANIFESTS.contains(&file_name(source)));
+    heredocs.is_empty() && !from_stage && !remote && !manifests
+}
+
+fn file_name(path : &str) -> &str {
+    let path = path.trim_end_matches('/');
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// The manifests the RUN steps read, in the order they do
+fn read_manifests(steps : &[Instruction]) -> Vec<String> {
+    let mut manifests : Vec<String> = Vec::new();
+    for step in steps {
+        if let Instruction::Run { command, .. } = step {
+            for word in command.to_string().split_whitespace() {
+                let name = file_name(word.trim_end_matches(';'));
+                if MANIFESTS.contains(&name) && !manifests.iter().any(|manifest| manifest == name) {
+                    manifests.push(name.to_string());
+                }
+            }
+        }
+    }
+    manifests
+}
+
+// The TCP ports of an EXPOSE argument such as 80 or 8000-8002/tcp; VS Code only forwards TCP
+fn expose_range(port : &str) -> Vec<u32> {
+    let (port, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+    if !protocol.eq_ignore_ascii_case("tcp") {
+        return Vec::new();
+    }
+    let (start, end) = match port.split_once('-') {
+        Some((start, end)) => (start.parse::<u32>(), end.parse::<u32>()),
+        None => (port.parse::<u32>(), port.parse::<u32>()),
+    };
+    match (start, end) {
+        (Ok(start), Ok(end)) if start <= end => (start..=end).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dev::*;
+    use crate::parser::parse;
+
+    const DOCKERFILE : &str = "FROM python:3.7-slim\n\
+                               WORKDIR /app\n\
+                               COPY requirements.txt ./\n\
+                               RUN pip install -r requirements.txt\n\
+                               COPY . .\n\
+                               COPY src/ src/\n\
+                               EXPOSE 80 53/udp\n\
+                               CMD [\"python\", \"app.py\"]\n";
+
+    fn options() -> Options {
+        Options { debug_ports: vec![5678], tools: vec![String::from("gdb")], ..Default::default() }
+    }
+
+    #[test]
+    fn mounts_the_sources_and_adds_the_tools() {
+        let dev = variant(&parse(DOCKERFILE).unwrap(), &options());
+        let rendered : Vec<String> = dev.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(rendered, vec![
+            "FROM python:3.7-slim",
+            "# Development tools",
+            "RUN apt-get update && apt-get install -y --no-install-recommends gdb && rm -rf /var/lib/apt/lists/*",
+            "EXPOSE 5678",
+            "WORKDIR /app",
+            "COPY requirements.txt ./",
+            "RUN pip install -r requirements.txt",
+            "# The sources are mounted here by the dev container",
+            "VOLUME /app",
+            "EXPOSE 80 53/udp",
+            "CMD [\"python\", \"app.py\"]",
+        ]);
+    }
+
+    #[test]
+    fn copies_the_manifests_the_sources_brought() {
+        let reference = parse(include_str!("../examples/test_reference/Dockerfile")).unwrap();
+        let dev = variant(&reference, &Options { tools: Vec::new(), ..Default::default() });
+        let rendered : Vec<String> = dev.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(&rendered[..13], &[
+            "# Use an official Python runtime as a parent image",
+            "FROM python:2.7-slim",
+            "",
+            "# Set the working directory to /app",
+            "WORKDIR /app",
+            "",
+            "# Copy the current directory contents into the container at /app",
+            "COPY requirements.txt /app/",
+            "# The sources are mounted here by the dev container",
+            "VOLUME /app",
+            "",
+            "# Install any needed packages specified in requirements.txt",
+            "RUN pip install --trusted-host pypi.python.org -r requirements.txt",
+        ]);
+        // Copied on their own already
+        let dev = variant(&parse("FROM python:3.7\nCOPY requirements.txt ./\nCOPY . .\nRUN pip install -r requirements.txt\n").unwrap(), &options());
+        assert_eq!(dev.iter().filter(|instruction| instruction.to_string().starts_with("COPY")).count(), 1);
+    }
+
+    #[test]
+    fn forwards_the_exposed_ports() {
+        let dev = variant(&parse(DOCKERFILE).unwrap(), &options());
+        let json : serde_json::Value = serde_json::from_str(&devcontainer(&dev, &options()).to_json()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "name": "dev",
+            "build": { "dockerfile": "../Dockerfile.dev", "context": ".." },
+            "workspaceMount": "source=${localWorkspaceFolder},target=/app,type=bind",
+            "workspaceFolder": "/app",
+            "forwardPorts": [5678, 80],
+        }));
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::instruction::{self, Instruction, Stage};
+
+// A semantic change between two Dockerfiles. Layers are counted from the FROM of their stage, which is layer 0.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    StageAdded { stage : usize, image : String },
+    StageRemoved { stage : usize, image : String },
+    BaseImageChanged { stage : usize, from : String, to : String },
+    PortAdded { stage : usize, port : String },
+    PortRemoved { stage : usize, port : String },
+    EnvAdded { stage : usize, key : String, value : String },
+    EnvRemoved { stage : usize, key : String, value : String },
+    EnvChanged { stage : usize, key : String, from : String, to : String },
+    InstructionInserted { stage : usize, layer : usize, instruction : String },
+    InstructionRemoved { stage : usize, layer : usize, instruction : String },
+    InstructionChanged { stage : usize, layer : usize, from : String, to : String },
+}
+
+// The layers of a stage which can no longer be taken from the build cache
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Invalidation {
+    pub stage       : usize,
+    pub first_layer : usize,
+    pub layers      : usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Default)]
+pub struct Diff {
+    pub changes     : Vec<Change>,
+    pub invalidated : Vec<Invalidation>,
+}
+
+enum Edit<'a> {
+    Keep,
+    Insert(&'a Instruction),
+    Remove(&'a Instruction),
+}
+
+// Compare two instruction models, either generated or parsed
+pub fn diff(old : &[Instruction], new : &[Instruction]) -> Diff {
+    let old_stages = instruction::stages(old);
+    let new_stages = instruction::stages(new);
+    let mut diff = Diff::default();
+    let mut first_invalid : Vec<Option<usize>> = vec![None; new_stages.len()];
+
+    for (index, new_stage) in new_stages.iter().enumerate() {
+        let old_stage = match old_stages.get(index) {
+            Some(old_stage) => old_stage,
+            None => {
+                diff.changes.push(Change::StageAdded { stage: index, image: new_stage.image.to_string() });
+                first_invalid[index] = Some(0);
+                continue;
+            },
+        };
+
+        if old_stage.image != new_stage.image {
+            diff.changes.push(Change::BaseImageChanged { stage: index, from: old_stage.image.to_string(), to: new_stage.image.to_string() });
+            first_invalid[index] = Some(0);
+        }
+
+        compare_ports(index, old_stage, new_stage, &mut diff.changes);
+        compare_env(index, old_stage, new_stage, &mut diff.changes);
+
+        let layer = compare_steps(index, old_stage, new_stage, &mut diff.changes);
+        first_invalid[index] = first_invalid[index].or(layer);
+    }
+
+    for (index, old_stage) in old_stages.iter().enumerate().skip(new_stages.len()) {
+        diff.changes.push(Change::StageRemoved { stage: index, image: old_stage.image.to_string() });
+    }
+
+    // A stage built on top of, or copying from, an invalidated stage is invalidated as well
+    for (index, stage) in new_stages.iter().enumerate() {
+        let depends_on = |name : &str| new_stages[..index].iter()
+            .any(|other| first_invalid[other.index].is_some() && (other.alias == Some(name) || other.index.to_string() == name));
+
+        let mut first = first_invalid[index];
+        if depends_on(stage.image) {
+            first = Some(0);
+        }
+        let copy = stage.steps().position(|step| match step.flag("from").and_then(|flag| flag.value.as_ref()) {
+            Some(from) => depends_on(from),
+            None => false,
+        });
+        if let Some(copy) = copy {
+            first = Some(first.map_or(copy + 1, |first| first.min(copy + 1)));
+        }
+        first_invalid[index] = first;
+
+        if let Some(first_layer) = first {
+            let total = stage.steps().count() + 1;
+            diff.invalidated.push(Invalidation { stage: index, first_layer, layers: total - first_layer });
+        }
+    }
+
+    diff
+}
+
+fn ports(stage : &Stage) -> BTreeSet<String> {
+    stage.steps()
+        .filter_map(|step| match step {
+            Instruction::Expose(ports) => Some(ports.iter().cloned()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn env(stage : &Stage) -> BTreeMap<String, String> {
+    stage.steps()
+        .filter_map(|step| match step {
+            Instruction::Env(pairs) => Some(pairs.iter().cloned()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn compare_ports(stage : usize, old : &Stage, new : &Stage, changes : &mut Vec<Change>) {
+    let (old, new) = (ports(old), ports(new));
+    for port in new.difference(&old) {
+        changes.push(Change::PortAdded { stage, port: port.clone() });
+    }
+    for port in old.difference(&new) {
+        changes.push(Change::PortRemoved { stage, port: port.clone() });
+    }
+}
+
+fn compare_env(stage : usize, old : &Stage, new : &Stage, changes : &mut Vec<Change>) {
+    let (old, new) = (env(old), env(new));
+    for (key, value) in &new {
+        match old.get(key) {
+            None => changes.push(Change::EnvAdded { stage, key: key.clone(), value: value.clone() }),
+            Some(old_value) if old_value != value => changes.push(Change::EnvChanged { stage, key: key.clone(), from: old_value.clone(), to: value.clone() }),
+            Some(_) => {},
+        }
+    }
+    for (key, value) in &old {
+        if !new.contains_key(key) {
+            changes.push(Change::EnvRemoved { stage, key: key.clone(), value: value.clone() });
+        }
+    }
+}
+
+// ENV and EXPOSE are already reported by their own kind of change
+fn reported_as_step(step : &Instruction) -> bool {
+    !matches!(step, Instruction::Env(_) | Instruction::Expose(_))
+}
+
+// Report the edits between the build steps of both stages, and return the first layer which differs.
+// A removal next to an insertion of the same instruction reads as a change.
+fn compare_steps(stage : usize, old : &Stage, new : &Stage, changes : &mut Vec<Change>) -> Option<usize> {
+    let old : Vec<&Instruction> = old.steps().collect();
+    let new : Vec<&Instruction> = new.steps().collect();
+    let edits = edits(&old, &new);
+
+    let mut first_layer = None;
+    let mut layer = 1;
+    let mut index = 0;
+    while index < edits.len() {
+        match (&edits[index], edits.get(index + 1)) {
+            (Edit::Keep, _) => {
+                layer += 1;
+            },
+            (Edit::Remove(from), Some(Edit::Insert(to))) | (Edit::Insert(to), Some(Edit::Remove(from))) if from.keyword() == to.keyword() => {
+                first_layer = first_layer.or(Some(layer));
+                if reported_as_step(from) || reported_as_step(to) {
+                    changes.push(Change::InstructionChanged { stage, layer, from: from.to_string(), to: to.to_string() });
+                }
+                layer += 1;
+                index += 1;
+            },
+            (Edit::Remove(removed), _) => {
+                first_layer = first_layer.or(Some(layer));
+                if reported_as_step(removed) {
+                    changes.push(Change::InstructionRemoved { stage, layer, instruction: removed.to_string() });
+                }
+            },
+            (Edit::Insert(inserted), _) => {
+                first_layer = first_layer.or(Some(layer));
+                if reported_as_step(inserted) {
+                    changes.push(Change::InstructionInserted { stage, layer, instruction: inserted.to_string() });
+                }
+                layer += 1;
+            },
+        }
+        index += 1;
+    }
+    first_layer
+}
+
+// Longest common subsequence of both step lists, as a list of edits
+fn edits<'a>(old : &[&'a Instruction], new : &[&'a Instruction]) -> Vec<Edit<'a>> {
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push(Edit::Keep);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lengths[i][j + 1] > lengths[i + 1][j]) {
+            edits.push(Edit::Insert(new[j]));
+            j += 1;
+        } else {
+            edits.push(Edit::Remove(old[i]));
+            i += 1;
+        }
+    }
+
+    edits
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.invalidated.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a diff is always serializable")
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::StageAdded { stage, image } => write!(f, "stage {}: added, based on {}", stage, image),
+            Change::StageRemoved { stage, image } => write!(f, "stage {}: removed, was based on {}", stage, image),
+            Change::BaseImageChanged { stage, from, to } => write!(f, "stage {}: base image changed from {} to {}", stage, from, to),
+            Change::PortAdded { stage, port } => write!(f, "stage {}: port {} exposed", stage, port),
+            Change::PortRemoved { stage, port } => write!(f, "stage {}: port {} no longer exposed", stage, port),
+            Change::EnvAdded { stage, key, value } => write!(f, "stage {}: env {} added with value {}", stage, key, value),
+            Change::EnvRemoved { stage, key, value } => write!(f, "stage {}: env {} removed, was {}", stage, key, value),
+            Change::EnvChanged { stage, key, from, to } => write!(f, "stage {}: env {} changed from {} to {}", stage, key, from, to),
+            Change::InstructionInserted { stage, layer, instruction } => write!(f, "stage {}: `{}` inserted at layer {}", stage, instruction, layer),
+            Change::InstructionRemoved { stage, layer, instruction } => write!(f, "stage {}: `{}` removed from layer {}", stage, instruction, layer),
+            Change::InstructionChanged { stage, layer, from, to } => write!(f, "stage {}: layer {} changed from `{}` to `{}`", stage, layer, from, to),
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no semantic changes");
+        }
+
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        for invalidation in &self.invalidated {
+            writeln!(f, "stage {}: cache invalidated from layer {} ({} layers rebuilt)",
+                     invalidation.stage, invalidation.first_layer, invalidation.layers)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn reports_semantic_changes_and_invalidated_layers() {
+        let old = parse("FROM python:2.7-slim\nWORKDIR /app\nCOPY . /app\nEXPOSE 80\nENV NAME World\nCMD python app.py\n").unwrap();
+        let new = parse("FROM python:2.7-slim\n# set up\nWORKDIR /app\nCOPY . /app\nRUN pip install -r requirements.txt\nEXPOSE 80 443\nENV NAME Docker\nCMD python app.py\n").unwrap();
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.changes, vec![
+            Change::PortAdded { stage: 0, port: String::from("443") },
+            Change::EnvChanged { stage: 0, key: String::from("NAME"), from: String::from("World"), to: String::from("Docker") },
+            Change::InstructionInserted { stage: 0, layer: 3, instruction: String::from("RUN pip install -r requirements.txt") },
+        ]);
+        assert_eq!(diff.invalidated, vec![Invalidation { stage: 0, first_layer: 3, layers: 4 }]);
+    }
+
+    #[test]
+    fn base_image_invalidates_dependent_stages() {
+        let old = parse("FROM rust:1.40 AS build\nRUN cargo build\nFROM debian:buster-slim\nRUN apt-get update\nCOPY --from=build /app /app\n").unwrap();
+        let new = parse("FROM rust:1.41 AS build\nRUN cargo build\nFROM debian:buster-slim\nRUN apt-get update\nCOPY --from=build /app /app\n").unwrap();
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.changes, vec![
+            Change::BaseImageChanged { stage: 0, from: String::from("rust:1.40"), to: String::from("rust:1.41") },
+        ]);
+        assert_eq!(diff.invalidated, vec![
+            Invalidation { stage: 0, first_layer: 0, layers: 2 },
+            Invalidation { stage: 1, first_layer: 2, layers: 1 },
+        ]);
+        assert!(diff.to_json().contains("\"kind\": \"base_image_changed\""));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::generator::DockerfileGenerator;
+use crate::instruction::{Command, Instruction};
+use crate::parser::{self, ParseError};
+
+// RUN instructions longer than this are split, one command per line
+const MAX_LINE_WIDTH : usize = 80;
+
+// Format a Dockerfile in the canonical style. Formatting an already formatted file returns it unchanged.
+pub fn format(source : &str) -> Result<String, ParseError> {
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+    let generator : DockerfileGenerator = normalize(parser::parse(source)?).into();
+
+    let mut formatted = String::new();
+    for instruction in generator.instructions() {
+        for line in render(instruction) {
+            formatted.push_str(&line);
+            formatted.push_str(newline);
+        }
+    }
+    Ok(formatted)
+}
+
+// The --check mode: is the file already in the canonical style
+pub fn check(source : &str) -> Result<bool, ParseError> {
+    Ok(format(source)? == source)
+}
+
+// Collapse runs of empty lines, drop them at both ends of the file, and tidy RUN commands
+fn normalize(instructions : Vec<Instruction>) -> Vec<Instruction> {
+    let mut normalized : Vec<Instruction> = Vec::new();
+    for instruction in instructions {
+        let instruction = match instruction {
+            Instruction::Empty if normalized.is_empty() || normalized.last() == Some(&Instruction::Empty) => continue,
+            Instruction::Run { flags, command: Command::Shell(line), heredocs } if heredocs.is_empty() => {
+                let commands : Vec<String> = split_commands(&line).iter().map(|command| sort_packages(command)).collect();
+                Instruction::Run { flags, command: Command::Shell(commands.join(" && ")), heredocs }
+            },
+            instruction => instruction,
+        };
+        normalized.push(instruction);
+    }
+
+    while normalized.last() == Some(&Instruction::Empty) {
+        normalized.pop();
+    }
+    normalized
+}
+
+fn render(instruction : &Instruction) -> Vec<String> {
+    let line = instruction.to_string();
+    let (flags, command) = match instruction {
+        Instruction::Run { flags, command: Command::Shell(command), heredocs } if heredocs.is_empty() && line.len() > MAX_LINE_WIDTH => (flags, command),
+        // Heredoc bodies span several lines
+        _ => return line.split('\n').map(String::from).collect(),
+    };
+
+    let commands = split_commands(command);
+    if commands.len() < 2 {
+        return vec![line];
+    }
+
+    let mut lines = Vec::new();
+    let mut first = String::from("RUN");
+    for flag in flags {
+        first.push(' ');
+        first.push_str(&flag.to_string());
+    }
+    first.push(' ');
+    first.push_str(&commands[0]);
+    lines.push(first);
+    for command in &commands[1..] {
+        lines.push(format!("    && {}", command));
+    }
+
+    // Align the continuation backslashes one space after the longest line
+    let width = lines[..lines.len() - 1].iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    let last = lines.len() - 1;
+    for line in &mut lines[..last] {
+        let padding = width - line.chars().count();
+        line.push_str(&" ".repeat(padding));
+        line.push_str(" \\");
+    }
+    lines
+}
+
+// Split a shell line on the && operators which are not quoted
+fn split_commands(line : &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut quote : Option<char> = None;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (_, '\\') => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            },
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                current.push(c);
+            },
+            (Some(open), c) if c == open => {
+                quote = None;
+                current.push(c);
+            },
+            (None, '&') if chars.peek() == Some(&'&') => {
+                chars.next();
+                commands.push(current.trim().to_string());
+                current.clear();
+            },
+            _ => current.push(c),
+        }
+    }
+
+    commands.push(current.trim().to_string());
+    commands
+}
+
+const APT_VALUE_OPTIONS : [&str; 8] = ["-t", "--target-release", "-o", "--option", "-c", "--config-file", "-a", "--host-architecture"];
+
+// Sort the packages of an `apt-get install` command, keeping its options first
+fn sort_packages(command : &str) -> String {
+    if command.contains(|c| "|;<>`'\"".contains(c)) || command.contains("$(") {
+        return command.to_string();
+    }
+
+    let words : Vec<&str> = command.split_whitespace().collect();
+    let install = match words.iter().position(|word| *word == "apt-get" || *word == "apt") {
+        Some(apt) => match words[apt..].iter().position(|word| *word == "install") {
+            Some(install) => apt + install,
+            None => return command.to_string(),
+        },
+        None => return command.to_string(),
+    };
+
+    // The options taking their value as the next word, such as `-t bookworm-backports`, keep it
+    let mut options = Vec::new();
+    let mut packages = Vec::new();
+    let mut rest = words[install + 1..].iter();
+    while let Some(word) = rest.next() {
+        if !word.starts_with('-') {
+            packages.push(*word);
+            continue;
+        }
+        options.push(*word);
+        if APT_VALUE_OPTIONS.contains(word) {
+            options.extend(rest.next());
+        }
+    }
+    packages.sort_unstable();
+    packages.dedup();
+
+    let mut sorted = words[..=install].to_vec();
+    sorted.extend(options);
+    sorted.extend(packages);
+    sorted.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formatter::*;
+
+    #[test]
+    fn formats_to_canonical_style() {
+        let source = "\n\nfrom python:3.7-slim\n\n\n\n#install packages\n\
+                      run apt-get update && apt-get install -y --no-install-recommends wget curl ca-certificates && rm -rf /var/lib/apt/lists/*\n\
+                      env NAME World\n\n";
+
+        let expected = "FROM python:3.7-slim\n\
+                        \n\
+                        # install packages\n\
+                        RUN apt-get update                                                          \\\n    \
+                            && apt-get install -y --no-install-recommends ca-certificates curl wget \\\n    \
+                            && rm -rf /var/lib/apt/lists/*\n\
+                        ENV NAME=World\n";
+
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert!(check(&formatted).unwrap());
+        assert!(!check(source).unwrap());
+    }
+
+    #[test]
+    fn keeps_short_chains_on_one_line() {
+        assert_eq!(format("RUN a&&b\r\n").unwrap(), "RUN a && b\r\n");
+        assert_eq!(format("RUN echo 'a && b'\n").unwrap(), "RUN echo 'a && b'\n");
+    }
+
+    #[test]
+    fn keeps_option_values_with_their_option() {
+        assert_eq!(format("RUN apt-get install -t trixie-backports wget curl\n").unwrap(),
+                   "RUN apt-get install -t trixie-backports curl wget\n");
+        assert_eq!(format("RUN apt-get install wget -o quiet=1 -y curl\n").unwrap(),
+                   "RUN apt-get install -o quiet=1 -y curl wget\n");
+        assert_eq!(format("RUN apt-get install --target-release=bookworm-backports wget curl\n").unwrap(),
+                   "RUN apt-get install --target-release=bookworm-backports curl wget\n");
+    }
+
+    #[test]
+    fn keeps_what_values_mean() {
+        assert_eq!(format("ENV A=\\$HOME\n").unwrap(), "ENV A=\\$HOME\n");
+        assert_eq!(format("ENV A=\"\\$HOME\"\n").unwrap(), "ENV A=\\$HOME\n");
+        assert_eq!(format("ENV A=\"$HOME/a b\" B='$x y'\n").unwrap(), "ENV A=\"$HOME/a b\" B=\"\\$x y\"\n");
+        assert_eq!(format("ARG A=\"\\\\$HOME\"\n").unwrap(), "ARG A=\"\\\\$HOME\"\n");
+    }
+}
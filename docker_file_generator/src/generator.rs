@@ -1,31 +1,54 @@
-use std::path::{ PathBuf };
-use std::fs::{File};
-use std::io::prelude::*;
-use std::io;
+use std::borrow::Cow;
+use std::path::{ Path, PathBuf };
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::fmt;
 
 use failure::Fail;
 
+use crate::builder::DockerfileBuilder;
+use crate::compat::{self, Incompatibilities, Policy, Target};
+use crate::dev;
+use crate::health::{self, HealthProbe};
+use crate::instruction::{Command, Flag, Heredoc, Instruction};
+use crate::oci::{self, ImageConfig};
+use crate::packages::PackageManager;
+use crate::parser;
+use crate::policy::{Audit, ImagePolicy};
+use crate::provenance::Provenance;
+use crate::security::{self, Finding};
+use crate::size::{self, Comparison, Estimate, SizeTable};
+use crate::visit::{Pipeline, Transform, Visitor};
+
+pub use crate::instruction::exec_form;
+
 #[derive(Fail, Debug)]
 pub enum GenerateError {
     #[fail(display = "Invalid argument was given.")]
     InvalidArgument(String),
     #[fail(display = "IO error has occur.")]
     IO(#[fail(cause)] io::Error),
+    #[fail(display = "{}", _0)]
+    Incompatible(Incompatibilities),
+    #[fail(display = "Images denied by the policy:\n{}", _0)]
+    Denied(Audit),
 }
 
+#[derive(Default)]
 pub struct DockerfileGenerator {
-    content : String,
-    path    : Option<PathBuf>
-}
-
-
-impl Default for DockerfileGenerator {
-    fn default() -> DockerfileGenerator {
-        DockerfileGenerator {
-            content: String::new(),
-            path : None
-        }
-    }
+    instructions : Vec<Instruction>,
+    path         : Option<PathBuf>,
+    target       : Option<(Target, Policy)>,
+    pipeline     : Pipeline,
+    policy       : Option<ImagePolicy>,
+    provenance   : Option<Provenance>,
+    health_probe : Option<HealthProbe>,
+    // Patterns of the .dockerignore written next to the Dockerfile
+    ignore       : Vec<String>,
+    // The owner given to the files copied after run_as_user, until the user or the stage changes
+    chown        : Option<String>,
+    // The text each instruction was pushed as, when it renders differently, so it is written the way it was given
+    verbatim     : Vec<Option<String>>,
 }
 
 impl DockerfileGenerator {
@@ -34,17 +57,204 @@ impl DockerfileGenerator {
         self
     }
 
+    pub fn output_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     pub fn generate(&mut self) -> Result<(), GenerateError> {
+        self.generate_changed().map(|_| ())
+    }
 
+    // Write the Dockerfile, and the .dockerignore if there are patterns, leaving the files which would not change untouched.
+    // Returns the files written.
+    pub fn generate_changed(&mut self) -> Result<Vec<PathBuf>, GenerateError> {
         let path = match self.path {
-            Some(ref p) => p,
+            Some(ref p) => p.clone(),
             None => return Err(GenerateError::InvalidArgument(String::from("No path was given"))),
         };
 
-        let mut docker_file =  File::create(path.as_path()).map_err(|e| GenerateError::IO(e))?;
+        let mut written = Vec::new();
+        if stream_if_changed(&path, self.lines()?).map_err(GenerateError::IO)? {
+            written.push(path.clone());
+        }
+        if let Some(dockerignore) = self.dockerignore() {
+            let ignore_path = path.with_file_name(".dockerignore");
+            if write_if_changed(&ignore_path, &dockerignore).map_err(GenerateError::IO)? {
+                written.push(ignore_path);
+            }
+        }
+        Ok(written)
+    }
+
+    // Exclude files matching the pattern from the build context
+    pub fn ignore(&mut self, pattern : &str) -> &mut DockerfileGenerator {
+        self.ignore.push(pattern.to_string());
+        self
+    }
+
+    pub fn dockerignore(&self) -> Option<String> {
+        if self.ignore.is_empty() {
+            return None;
+        }
+        Some(self.ignore.iter().map(|pattern| format!("{}\n", pattern)).collect())
+    }
+
+    // Build for a specific tool, instead of whatever the Dockerfile happens to use
+    pub fn target(&mut self, target : Target, policy : Policy) -> &mut DockerfileGenerator {
+        self.target = Some((target, policy));
+        self
+    }
+
+    // Add a pass to the pipeline run over the instructions when the Dockerfile is rendered
+    pub fn transform<T : Transform + 'static>(&mut self, pass : T) -> &mut DockerfileGenerator {
+        self.pipeline.pass(pass);
+        self
+    }
+
+    // Rewrite the images to the policy's mirrors and replacements, and refuse to render denied ones
+    pub fn image_policy(&mut self, policy : ImagePolicy) -> &mut DockerfileGenerator {
+        self.policy = Some(policy);
+        self
+    }
+
+    // Synthesize the HEALTHCHECK of the final stage when rendering, once every instruction is known
+    pub fn health_probe(&mut self, probe : HealthProbe) -> &mut DockerfileGenerator {
+        self.health_probe = Some(probe);
+        self
+    }
+
+    // Record the provenance in a header and labels when rendering
+    pub fn provenance(&mut self, provenance : Provenance) -> &mut DockerfileGenerator {
+        self.provenance = Some(provenance);
+        self
+    }
+
+    pub fn visit<V : Visitor>(&self, visitor : &mut V) -> &DockerfileGenerator {
+        visitor.visit(&self.instructions);
+        self
+    }
+
+    // The Dockerfile as it is generated: transformed by the pipeline and the image policy, given its health check,
+    // then checked against the target and downgraded if allowed, and finally given its provenance
+    pub fn render(&mut self) -> Result<String, GenerateError> {
+        let mut rendered = String::new();
+        for line in self.lines()? {
+            rendered.push_str(&line);
+            rendered.push_str("\r\n");
+        }
+        Ok(rendered)
+    }
+
+    // The lines of the generated Dockerfile, without their line endings, rendered one instruction at a time
+    pub fn lines(&mut self) -> Result<Lines<'_>, GenerateError> {
+        let (instructions, verbatim) = match self.prepare()? {
+            Some(instructions) => {
+                let verbatim = unchanged(&self.instructions, &self.verbatim, &instructions);
+                (Cow::Owned(instructions), Cow::Owned(verbatim))
+            },
+            None => (Cow::Borrowed(&self.instructions[..]), Cow::Borrowed(&self.verbatim[..])),
+        };
+        check_values(&instructions)?;
+        Ok(Lines { instructions, verbatim, next: 0, pending: Vec::new().into_iter() })
+    }
+
+    // Stream the generated Dockerfile, so it is never held in memory as a whole
+    pub fn write_to<W : io::Write>(&mut self, writer : &mut W) -> Result<(), GenerateError> {
+        for line in self.lines()? {
+            writer.write_all(line.as_bytes()).map_err(GenerateError::IO)?;
+            writer.write_all(b"\r\n").map_err(GenerateError::IO)?;
+        }
+        writer.flush().map_err(GenerateError::IO)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub async fn write_async<W : tokio::io::AsyncWrite + Unpin>(&mut self, writer : &mut W) -> Result<(), GenerateError> {
+        use tokio::io::AsyncWriteExt;
+
+        for line in self.lines()? {
+            writer.write_all(line.as_bytes()).await.map_err(GenerateError::IO)?;
+            writer.write_all(b"\r\n").await.map_err(GenerateError::IO)?;
+        }
+        writer.flush().await.map_err(GenerateError::IO)
+    }
+
+    // The instructions to render, or None when there is nothing to apply to them
+    fn prepare(&mut self) -> Result<Option<Vec<Instruction>>, GenerateError> {
+        if self.pipeline.is_empty() && self.target.is_none() && self.policy.is_none() && self.health_probe.is_none() && self.provenance.is_none() {
+            return Ok(None);
+        }
+
+        let mut instructions = self.pipeline.transform(self.instructions.clone());
+        if let Some(ref policy) = self.policy {
+            let (rewritten, audit) = policy.apply(&instructions);
+            if audit.is_denied() {
+                return Err(GenerateError::Denied(audit));
+            }
+            instructions = rewritten;
+        }
+        if let Some(ref probe) = self.health_probe {
+            let healthcheck = health::synthesize(&instructions, probe)?;
+            instructions.push(healthcheck);
+        }
+        let instructions = match self.target {
+            None => instructions,
+            Some((target, Policy::Reject)) => {
+                let items = compat::check(&instructions, target);
+                if !items.is_empty() {
+                    return Err(GenerateError::Incompatible(Incompatibilities { target, items }));
+                }
+                instructions
+            },
+            Some((target, Policy::Downgrade)) => compat::downgrade(&instructions, target).map_err(GenerateError::Incompatible)?,
+        };
+        let instructions = match self.provenance {
+            Some(ref provenance) => provenance.apply(instructions),
+            None => instructions,
+        };
+        Ok(Some(instructions))
+    }
 
-        let result = docker_file.write_all( self.content.to_string().as_bytes()).map_err(|e| GenerateError::IO(e))?;
-        Ok(result)
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn builder() -> DockerfileBuilder {
+        DockerfileBuilder::default()
+    }
+
+    // Run the closure on the generator only if the condition holds, without breaking the chain
+    pub fn when<F>(&mut self, condition : bool, f : F) -> &mut DockerfileGenerator
+        where F: FnOnce(&mut DockerfileGenerator) -> &mut DockerfileGenerator {
+        if condition {
+            f(self);
+        }
+        self
+    }
+
+    pub fn when_else<F, G>(&mut self, condition : bool, then : F, otherwise : G) -> &mut DockerfileGenerator
+        where F: FnOnce(&mut DockerfileGenerator) -> &mut DockerfileGenerator,
+              G: FnOnce(&mut DockerfileGenerator) -> &mut DockerfileGenerator {
+        if condition {
+            then(self);
+        } else {
+            otherwise(self);
+        }
+        self
+    }
+
+    pub fn for_each<I, F>(&mut self, items : I, mut f : F) -> &mut DockerfileGenerator
+        where I: IntoIterator,
+              F: FnMut(&mut DockerfileGenerator, I::Item) -> &mut DockerfileGenerator {
+        for item in items {
+            f(self, item);
+        }
+        self
+    }
+
+    pub fn apply<F>(&mut self, f : F) -> &mut DockerfileGenerator
+        where F: FnOnce(&mut DockerfileGenerator) -> &mut DockerfileGenerator {
+        f(self);
+        self
     }
 
     pub fn comment(& mut self, line : &str) -> &mut DockerfileGenerator {
@@ -79,6 +289,43 @@ impl DockerfileGenerator {
         self.push(&full_line[..])
     }
 
+    // Run a script written inline, passed to the shell as is. A shebang line picks another interpreter.
+    pub fn run_script(& mut self, script : &str) -> &mut DockerfileGenerator {
+        let heredoc = Heredoc::new(script);
+        self.push_instruction(Instruction::Run {
+            flags: Vec::new(),
+            command: Command::Shell(heredoc.marker()),
+            heredocs: vec![heredoc],
+        })
+    }
+
+    // Install system packages with the package manager of the current stage's base image
+    pub fn install(& mut self, packages : &[&str]) -> &mut DockerfileGenerator {
+        let manager = PackageManager::for_stage(&self.instructions);
+        self.install_with(manager, packages)
+    }
+
+    pub fn install_with(& mut self, manager : PackageManager, packages : &[&str]) -> &mut DockerfileGenerator {
+        self.run(&manager.install(packages))
+    }
+
+    // Run a command needing build dependencies, which are removed in the same layer
+    pub fn install_build_deps(& mut self, build : &[&str], command : &str) -> &mut DockerfileGenerator {
+        let manager = PackageManager::for_stage(&self.instructions);
+        self.run(&manager.with_build_deps(build, command))
+    }
+
+    // Create a file in the image with the given content, without it having to exist in the build context
+    pub fn copy_content(& mut self, content : &str, to : &str) -> &mut DockerfileGenerator {
+        let heredoc = Heredoc::new(content);
+        self.push_instruction(Instruction::Copy {
+            flags: Vec::new(),
+            sources: vec![heredoc.marker()],
+            destination: to.to_string(),
+            heredocs: vec![heredoc],
+        })
+    }
+
     pub fn expose(& mut self, port : u32) -> &mut DockerfileGenerator {
         let mut full_line = String::from("EXPOSE ");
         full_line.push_str(&port.to_string());
@@ -99,13 +346,536 @@ impl DockerfileGenerator {
         self.push(&full_line[..])
     }
 
+    pub fn entrypoint(& mut self, line : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("ENTRYPOINT ");
+        full_line.push_str(line);
+        self.push(&full_line[..])
+    }
+
+    pub fn add(& mut self, from : &str, to : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("ADD ");
+        full_line.push_str(from);
+        full_line.push(' ');
+        full_line.push_str(to);
+        self.push(&full_line[..])
+    }
+
+    pub fn user(& mut self, line : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("USER ");
+        full_line.push_str(line);
+        self.push(&full_line[..])
+    }
+
+    pub fn label(& mut self, key : &str, value : &str) -> &mut DockerfileGenerator {
+        self.push_instruction(Instruction::Label(vec![(key.to_string(), value.to_string())]))
+    }
+
+    pub fn volume(& mut self, line : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("VOLUME ");
+        full_line.push_str(line);
+        self.push(&full_line[..])
+    }
+
+    pub fn arg(& mut self, line : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("ARG ");
+        full_line.push_str(line);
+        self.push(&full_line[..])
+    }
+
+    pub fn stop_signal(& mut self, signal : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("STOPSIGNAL ");
+        full_line.push_str(signal);
+        self.push(&full_line[..])
+    }
+
+    pub fn healthcheck(& mut self, line : &str) -> &mut DockerfileGenerator {
+        let mut full_line = String::from("HEALTHCHECK ");
+        full_line.push_str(line);
+        self.push(&full_line[..])
+    }
+
+    pub fn image_config(&self) -> ImageConfig {
+        oci::preview(&self.instructions)
+    }
+
+    // Create the user and its group, give it the WORKDIR and switch to it. The files copied afterwards belong to it.
+    pub fn run_as_user(& mut self, name : &str, uid : u32, gid : u32) -> &mut DockerfileGenerator {
+        let manager = PackageManager::for_stage(&self.instructions);
+        let useradd = format!("groupadd --system --gid {gid} {name} && useradd --system --uid {uid} --gid {gid} --no-create-home {name}",
+                              name = name, uid = uid, gid = gid);
+        let mut command = match manager {
+            PackageManager::Apk => format!("addgroup -S -g {gid} {name} && adduser -S -D -H -u {uid} -G {name} {name}", name = name, uid = uid, gid = gid),
+            // The minimal UBI images come without shadow-utils
+            PackageManager::Microdnf => format!("{} && {}", manager.install(&["shadow-utils"]), useradd),
+            _ => useradd,
+        };
+        if let Some(dir) = self.work_dir_of_stage() {
+            command.push_str(&format!(" && chown {}:{} {}", uid, gid, dir));
+        }
+        self.run(&command).user(name);
+        self.chown = Some(format!("{}:{}", uid, gid));
+        self
+    }
+
+    // Security findings, including the steps which need root after the switch to another user
+    pub fn scan(&self) -> Vec<Finding> {
+        security::scan(&self.instructions)
+    }
+
+    // The size of the image, from the sizes known offline
+    pub fn estimate_size(&self, table : &SizeTable) -> Estimate {
+        size::estimate(&self.instructions, table)
+    }
+
+    // The size of the image on other base images, and what they would break
+    pub fn compare_bases(&self, candidates : &[&str], table : &SizeTable) -> Comparison {
+        size::compare(&self.instructions, candidates, table)
+    }
+
+    // The development variant of the final stage, see dev::variant
+    pub fn dev(&self, options : &dev::Options) -> DockerfileGenerator {
+        dev::variant(&self.instructions, options).into()
+    }
+
     pub fn empty_line(& mut self) -> &mut DockerfileGenerator {
         self.push("")
     }
 
+    // Lines which can not be parsed as an instruction are still written as is
     pub fn push(& mut self, line : &str) -> & mut DockerfileGenerator{
-        self.content.push_str(line);
-        self.content.push_str("\r\n");
+        let instruction = parser::parse_line(line).unwrap_or_else(|_| Instruction::Raw(line.to_string()));
+        let verbatim = instruction.to_string() != line;
+        let pushed = instruction.clone();
+        self.push_instruction(instruction);
+        // Unless pushing it changed it, such as a COPY given the owner of run_as_user
+        if verbatim && self.instructions.last() == Some(&pushed) {
+            *self.verbatim.last_mut().unwrap() = Some(line.to_string());
+        }
+        self
+    }
+
+    pub fn push_instruction(& mut self, mut instruction : Instruction) -> & mut DockerfileGenerator{
+        match &mut instruction {
+            Instruction::From { .. } | Instruction::User(_) => self.chown = None,
+            Instruction::Copy { flags, .. } | Instruction::Add { flags, .. } => if let Some(owner) = &self.chown {
+                if !flags.iter().any(|flag| flag.name == "chown") {
+                    flags.push(Flag { name: String::from("chown"), value: Some(owner.clone()) });
+                }
+            },
+            _ => {},
+        }
+        self.instructions.push(instruction);
+        self.verbatim.push(None);
         self
     }
-}
\ No newline at end of file
+
+    // The WORKDIR in effect at the end of the current stage, relative ones resolved against the previous ones
+    fn work_dir_of_stage(&self) -> Option<String> {
+        let start = self.instructions.iter().rposition(|instruction| matches!(instruction, Instruction::From { .. })).unwrap_or(0);
+        self.instructions[start..].iter().fold(None, |current : Option<String>, instruction| match (instruction, current) {
+            (Instruction::WorkDir(dir), _) if dir.starts_with('/') => Some(dir.clone()),
+            (Instruction::WorkDir(dir), Some(current)) => Some(format!("{}/{}", current.trim_end_matches('/'), dir)),
+            (Instruction::WorkDir(dir), None) => Some(format!("/{}", dir)),
+            (_, current) => current,
+        })
+    }
+}
+
+// Write the file unless it already has this content, so tools watching it are not triggered for nothing
+pub fn write_if_changed(path : &Path, content : &str) -> io::Result<bool> {
+    match fs::read(path) {
+        Ok(current) if current == content.as_bytes() => Ok(false),
+        _ => fs::write(path, content).map(|_| true),
+    }
+}
+
+// Stream the lines over the file only if their content differ from it, so nothing is created when they do not.
+// They are compared with the file as they come, then written to a temporary file next to it, which replaces it.
+fn stream_if_changed<I : Iterator<Item = String>>(path : &Path, mut lines : I) -> io::Result<bool> {
+    // The length of the content found in the file, and the first line which differs from it
+    let mut same = 0;
+    let mut changed = None;
+    match fs::File::open(path) {
+        Ok(file) => {
+            let mut current = io::BufReader::new(file);
+            let mut read = Vec::new();
+            for line in lines.by_ref() {
+                let line = format!("{}\r\n", line).into_bytes();
+                read.resize(line.len(), 0);
+                match current.read_exact(&mut read) {
+                    Ok(()) if read == line => same += line.len() as u64,
+                    Ok(()) => { changed = Some(line); break },
+                    Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => { changed = Some(line); break },
+                    Err(error) => return Err(error),
+                }
+            }
+            if changed.is_none() && current.fill_buf()?.is_empty() {
+                return Ok(false);
+            }
+        },
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {},
+        Err(error) => return Err(error),
+    }
+
+    let temporary = temporary_path(path);
+    let written = write_changed(path, &temporary, same, changed, lines).and_then(|_| fs::rename(&temporary, path));
+    if let Err(error) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(error);
+    }
+    Ok(true)
+}
+
+// The file the content is written to before it replaces the one at path
+pub(crate) fn temporary_path(path : &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+// Write the content the file starts with, then the lines from the first one which changed
+fn write_changed<I : Iterator<Item = String>>(path : &Path, temporary : &Path, same : u64, changed : Option<Vec<u8>>, lines : I) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(fs::File::create(temporary)?);
+    if same > 0 {
+        io::copy(&mut fs::File::open(path)?.take(same), &mut writer)?;
+    }
+    for line in changed.into_iter().chain(lines.map(|line| format!("{}\r\n", line).into_bytes())) {
+        writer.write_all(&line)?;
+    }
+    writer.flush()
+}
+
+impl From<Vec<Instruction>> for DockerfileGenerator {
+    fn from(instructions : Vec<Instruction>) -> DockerfileGenerator {
+        DockerfileGenerator {
+            verbatim: vec![None; instructions.len()],
+            instructions,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct Lines<'a> {
+    instructions : Cow<'a, [Instruction]>,
+    verbatim     : Cow<'a, [Option<String>]>,
+    next         : usize,
+    // The lines left of the current instruction, which has several when it has heredocs
+    pending      : std::vec::IntoIter<String>,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.pending.next() {
+                return Some(line);
+            }
+            let instruction = self.instructions.get(self.next)?;
+            let rendered : Vec<String> = render(instruction, &self.verbatim[self.next]).split('\n').map(String::from).collect();
+            self.next += 1;
+            self.pending = rendered.into_iter();
+        }
+    }
+}
+
+// A control character, such as a newline, can not be written in a value of ENV, LABEL or ARG: nothing escapes it
+fn check_values(instructions : &[Instruction]) -> Result<(), GenerateError> {
+    for instruction in instructions {
+        let pairs : Vec<(&String, Option<&String>)> = match instruction {
+            Instruction::Env(pairs) | Instruction::Label(pairs) => pairs.iter().map(|(key, value)| (key, Some(value))).collect(),
+            Instruction::Arg { name, default } => vec![(name, default.as_ref())],
+            Instruction::OnBuild(trigger) => {
+                check_values(std::slice::from_ref(&**trigger))?;
+                continue;
+            },
+            _ => continue,
+        };
+        let control = |text : &str| text.chars().any(|c| c.is_control() && c != '\t');
+        if let Some((key, _)) = pairs.iter().find(|(key, value)| control(key) || value.is_some_and(|value| control(value))) {
+            return Err(GenerateError::InvalidArgument(format!("{} {:?} has a control character, which a Dockerfile can not hold",
+                                                              instruction.keyword().unwrap(), key)));
+        }
+    }
+    Ok(())
+}
+
+// The instruction as it was pushed, unless it was changed since
+fn render(instruction : &Instruction, verbatim : &Option<String>) -> String {
+    verbatim.clone().unwrap_or_else(|| instruction.to_string())
+}
+
+// The text of the prepared instructions the passes left as they were pushed. They are matched in order,
+// so an instruction added, removed or changed by a pass drops no other instruction's text.
+fn unchanged(pushed : &[Instruction], verbatim : &[Option<String>], prepared : &[Instruction]) -> Vec<Option<String>> {
+    let mut next = 0;
+    prepared.iter().map(|instruction| {
+        let found = pushed[next..].iter().position(|other| other == instruction)?;
+        next += found + 1;
+        verbatim[next - 1].clone()
+    }).collect()
+}
+
+impl fmt::Display for DockerfileGenerator {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        for (instruction, verbatim) in self.instructions.iter().zip(&self.verbatim) {
+            // Heredoc bodies are written with the same line endings as the rest of the file
+            write!(f, "{}\r\n", render(instruction, verbatim).replace('\n', "\r\n"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::*;
+    use crate::provenance::Provenance;
+
+    #[test]
+    fn combinators_keep_the_chain() {
+        let mut generator = DockerfileGenerator::default();
+        generator.when(false, |g| g.comment("skipped"))
+            .when_else(true, |g| g.from("python:3.7-slim"), |g| g.from("python:2.7-slim"))
+            .for_each(&[80, 443], |g, port| g.expose(*port))
+            .apply(|g| g.cmd("python app.py"));
+
+        assert_eq!(generator.to_string(), "FROM python:3.7-slim\r\nEXPOSE 80\r\nEXPOSE 443\r\nCMD python app.py\r\n");
+    }
+
+    #[test]
+    fn builder_matches_generator() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python:2.7-slim")
+            .work_dir("/app")
+            .env("NAME", "World");
+
+        let built = DockerfileGenerator::builder()
+            .when(true, |b| b.from("python:2.7-slim"))
+            .work_dir("/app")
+            .for_each(vec![("NAME", "World")], |b, (key, value)| b.env(key, value))
+            .build();
+
+        assert_eq!(built.to_string(), generator.to_string());
+    }
+
+    #[test]
+    fn dockerfile_macro_expands_to_generator_calls() {
+        let version = 3;
+        let port = 80;
+        let generator = crate::dockerfile! {
+            # "Use an official Python runtime as a parent image";
+            FROM format!("python:{}.7-slim", version);
+            WORKDIR "/app";
+            COPY "." "/app";
+            RUN "pip install -r requirements.txt";
+            EXPOSE port;
+            ENV NAME = "World";
+            CMD ["python", "app.py"];
+        };
+
+        assert_eq!(generator.to_string(), "# Use an official Python runtime as a parent image\r\n\
+                                       FROM python:3.7-slim\r\n\
+                                       WORKDIR /app\r\n\
+                                       COPY . /app\r\n\
+                                       RUN pip install -r requirements.txt\r\n\
+                                       EXPOSE 80\r\n\
+                                       ENV NAME World\r\n\
+                                       CMD [\"python\", \"app.py\"]\r\n");
+    }
+
+    #[test]
+    fn dockerfile_macro_covers_the_other_instructions() {
+        let generator = crate::dockerfile! {
+            ARG "VERSION=3.7";
+            FROM "python:${VERSION}-slim";
+            LABEL "org.opencontainers.image.source" = "https://example.com/app";
+            LABEL maintainer = "team";
+            ADD "app.tgz" "/app";
+            VOLUME "/data";
+            USER "app";
+            STOPSIGNAL "SIGINT";
+            HEALTHCHECK "CMD curl -f http://localhost/";
+            ENTRYPOINT ["python"];
+        };
+
+        assert_eq!(generator.to_string(), "ARG VERSION=3.7\r\n\
+                                       FROM python:${VERSION}-slim\r\n\
+                                       LABEL org.opencontainers.image.source=https://example.com/app\r\n\
+                                       LABEL maintainer=team\r\n\
+                                       ADD app.tgz /app\r\n\
+                                       VOLUME /data\r\n\
+                                       USER app\r\n\
+                                       STOPSIGNAL SIGINT\r\n\
+                                       HEALTHCHECK CMD curl -f http://localhost/\r\n\
+                                       ENTRYPOINT [\"python\"]\r\n");
+    }
+
+    #[test]
+    fn target_rejects_or_downgrades() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python:3.7-slim")
+            .copy("--chmod=755 entrypoint.sh", "/usr/local/bin/entrypoint.sh");
+
+        assert_eq!(generator.render().unwrap(), generator.to_string());
+
+        generator.target(Target::LegacyDocker, Policy::Reject);
+        match generator.render() {
+            Err(GenerateError::Incompatible(incompatibilities)) => assert_eq!(incompatibilities.items.len(), 1),
+            _ => panic!("COPY --chmod should be rejected"),
+        }
+
+        generator.target(Target::LegacyDocker, Policy::Downgrade);
+        assert_eq!(generator.render().unwrap(), "FROM python:3.7-slim\r\n\
+                                                 COPY entrypoint.sh /usr/local/bin/entrypoint.sh\r\n\
+                                                 RUN chmod 755 /usr/local/bin/entrypoint.sh\r\n");
+    }
+
+    #[test]
+    fn heredocs_pick_a_free_delimiter() {
+        let mut generator = DockerfileGenerator::default();
+        generator.run_script("set -e\necho $HOME")
+            .copy_content("[app]\nEOF\nname = \"demo\"\n", "/etc/app.conf");
+
+        assert_eq!(generator.to_string(), "RUN <<\"EOF\"\r\nset -e\r\necho $HOME\r\nEOF\r\n\
+                                           COPY <<\"EOF1\" /etc/app.conf\r\n[app]\r\nEOF\r\nname = \"demo\"\r\nEOF1\r\n");
+        assert_eq!(parser::parse(&generator.to_string()).unwrap(), generator.instructions());
+    }
+
+    // Tag the images which have none as latest
+    struct Pin;
+
+    impl Transform for Pin {
+        fn transform_from(&mut self, image : &mut String, _alias : &mut Option<String>, _flags : &mut Vec<crate::instruction::Flag>) {
+            if !image.contains(':') {
+                image.push_str(":latest");
+            }
+        }
+    }
+
+    #[test]
+    fn render_runs_the_transforms() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python").transform(Pin);
+        assert_eq!(generator.render().unwrap(), "FROM python:latest\r\n");
+        assert_eq!(generator.to_string(), "FROM python\r\n");
+    }
+
+    #[test]
+    fn writes_lines_the_way_they_were_pushed() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python  AS base").env("NAME", "World").transform(Pin);
+        assert_eq!(generator.to_string(), "FROM python  AS base\r\nENV NAME World\r\n");
+        assert_eq!(generator.render().unwrap(), "FROM python:latest AS base\r\nENV NAME World\r\n");
+
+        // Each line keeps its own text, even when another renders the same
+        let mut generator = DockerfileGenerator::default();
+        generator.push("FROM python  AS base").from("python AS base").push("EXPOSE  80").transform(Pin);
+        assert_eq!(generator.to_string(), "FROM python  AS base\r\nFROM python AS base\r\nEXPOSE  80\r\n");
+        assert_eq!(generator.render().unwrap(), "FROM python:latest AS base\r\nFROM python:latest AS base\r\nEXPOSE  80\r\n");
+    }
+
+    #[test]
+    fn install_uses_the_stage_package_manager() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python:3.7-alpine AS build")
+            .install_build_deps(&["gcc", "musl-dev"], "pip install -r requirements.txt")
+            .from("python:3.7-slim")
+            .install(&["curl"])
+            .install_with(PackageManager::Pip, &["gunicorn=21.2.0"]);
+
+        assert_eq!(generator.to_string(), "FROM python:3.7-alpine AS build\r\n\
+                                           RUN apk add --no-cache --virtual .build-deps gcc musl-dev && pip install -r requirements.txt && apk del .build-deps\r\n\
+                                           FROM python:3.7-slim\r\n\
+                                           RUN apt-get update && apt-get install -y --no-install-recommends curl && rm -rf /var/lib/apt/lists/*\r\n\
+                                           RUN pip install --no-cache-dir gunicorn==21.2.0\r\n");
+    }
+
+    #[test]
+    fn run_as_user_owns_the_later_copies() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python:3.7-alpine")
+            .work_dir("/srv")
+            .work_dir("app")
+            .copy("requirements.txt", ".")
+            .run_as_user("app", 1000, 1000)
+            .copy(".", ".")
+            .copy("--chown=root:root config.toml", "/etc/app/")
+            .from("debian:bookworm-slim")
+            .run_as_user("app", 10001, 10001)
+            .install(&["curl"])
+            .user("root")
+            .copy("--from=0 /srv/app", "/app");
+
+        assert_eq!(generator.to_string(), "FROM python:3.7-alpine\r\n\
+                                           WORKDIR /srv\r\n\
+                                           WORKDIR app\r\n\
+                                           COPY requirements.txt .\r\n\
+                                           RUN addgroup -S -g 1000 app && adduser -S -D -H -u 1000 -G app app && chown 1000:1000 /srv/app\r\n\
+                                           USER app\r\n\
+                                           COPY --chown=1000:1000 . .\r\n\
+                                           COPY --chown=root:root config.toml /etc/app/\r\n\
+                                           FROM debian:bookworm-slim\r\n\
+                                           RUN groupadd --system --gid 10001 app && useradd --system --uid 10001 --gid 10001 --no-create-home app\r\n\
+                                           USER app\r\n\
+                                           RUN apt-get update && apt-get install -y --no-install-recommends curl && rm -rf /var/lib/apt/lists/*\r\n\
+                                           USER root\r\n\
+                                           COPY --from=0 /srv/app /app\r\n");
+
+        let findings : Vec<usize> = generator.scan().iter()
+            .filter(|finding| finding.rule == security::Rule::RootAfterUser)
+            .map(|finding| finding.line)
+            .collect();
+        assert_eq!(findings, vec![12]);
+    }
+
+    #[test]
+    fn run_as_user_installs_useradd_on_minimal_images() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("registry.access.redhat.com/ubi9/ubi-minimal")
+            .run_as_user("app", 1001, 1001);
+
+        assert_eq!(generator.to_string(), "FROM registry.access.redhat.com/ubi9/ubi-minimal\r\n\
+                                           RUN microdnf install -y --setopt=install_weak_deps=0 shadow-utils && microdnf clean all \
+                                           && groupadd --system --gid 1001 app && useradd --system --uid 1001 --gid 1001 --no-create-home app\r\n\
+                                           USER app\r\n");
+    }
+
+    #[test]
+    fn streams_the_rendered_lines() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python:3.7-slim")
+            .run_script("set -e\npip install flask")
+            .provenance(Provenance::default());
+
+        let lines : Vec<String> = generator.lines().unwrap().collect();
+        assert_eq!(lines[2], "FROM python:3.7-slim");
+        assert_eq!(&lines[3..7], ["RUN <<\"EOF\"", "set -e", "pip install flask", "EOF"]);
+        assert!(lines[7].starts_with("LABEL io.dock-gen.version="));
+
+        let mut streamed = Vec::new();
+        generator.write_to(&mut streamed).unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), generator.render().unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        generator.path(dir.path().join("Dockerfile"));
+        assert_eq!(generator.generate_changed().unwrap().len(), 1);
+        assert!(generator.generate_changed().unwrap().is_empty());
+        assert_eq!(fs::read_to_string(dir.path().join("Dockerfile")).unwrap(), generator.render().unwrap());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn writes_to_an_async_writer() {
+        let mut generator = DockerfileGenerator::default();
+        generator.from("python:3.7-slim").cmd("python app.py");
+
+        let mut written = Vec::new();
+        generator.write_async(&mut written).await.unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "FROM python:3.7-slim\r\nCMD python app.py\r\n");
+    }
+
+    #[test]
+    fn exec_form_escapes_arguments() {
+        assert_eq!(exec_form(&["echo", "say \"hi\"", "C:\\"]), r#"["echo", "say \"hi\"", "C:\\"]"#);
+    }
+}
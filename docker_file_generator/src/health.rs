@@ -0,0 +1,266 @@
+use std::time::Duration;
+
+use crate::generator::GenerateError;
+use crate::instruction::{self, Command, Flag, Instruction};
+use crate::packages::{self, PackageManager};
+
+// Official images built on buildpack-deps, which ship curl and wget unless they are slim
+const FULL_IMAGES : [&str; 7] = ["buildpack-deps", "python", "node", "ruby", "golang", "rust", "perl"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Check {
+    // An HTTP GET on localhost, healthy when the status is below 400
+    Http { path : String, port : u32 },
+    // A TCP connection on localhost
+    Tcp { port : u32 },
+    Command(String),
+}
+
+// A HEALTHCHECK to synthesize for the final stage, with the tool its base image provides
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthProbe {
+    pub check        : Check,
+    pub interval     : Option<Duration>,
+    pub timeout      : Option<Duration>,
+    pub start_period : Option<Duration>,
+    pub retries      : Option<u32>,
+}
+
+// The tools a check can run with, by order of preference
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tool {
+    Curl,
+    Wget,
+    Python { version : u32 },
+    Node,
+    // Busybox nc, on Alpine
+    Netcat,
+    // Bash's /dev/tcp, on the other distributions
+    Bash,
+}
+
+impl HealthProbe {
+    pub fn http(path : &str, port : u32) -> HealthProbe {
+        HealthProbe::new(Check::Http { path: path.to_string(), port })
+    }
+
+    pub fn tcp(port : u32) -> HealthProbe {
+        HealthProbe::new(Check::Tcp { port })
+    }
+
+    pub fn command(command : &str) -> HealthProbe {
+        HealthProbe::new(Check::Command(command.to_string()))
+    }
+
+    fn new(check : Check) -> HealthProbe {
+        HealthProbe { check, interval: None, timeout: None, start_period: None, retries: None }
+    }
+
+    pub fn interval(mut self, interval : Duration) -> HealthProbe {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn timeout(mut self, timeout : Duration) -> HealthProbe {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn start_period(mut self, start_period : Duration) -> HealthProbe {
+        self.start_period = Some(start_period);
+        self
+    }
+
+    pub fn retries(mut self, retries : u32) -> HealthProbe {
+        self.retries = Some(retries);
+        self
+    }
+
+    fn port(&self) -> Option<u32> {
+        match self.check {
+            Check::Http { port, .. } | Check::Tcp { port } => Some(port),
+            Check::Command(_) => None,
+        }
+    }
+}
+
+// The HEALTHCHECK of the final stage, which must not have one yet. The port must be exposed by the stage or the stages it is built on,
+// and their base image or the packages they install must provide a tool to run the check.
+pub fn synthesize(instructions : &[Instruction], probe : &HealthProbe) -> Result<Instruction, GenerateError> {
+    let stages = instruction::stages(instructions);
+    let ancestry = instruction::ancestry(&stages);
+    let (stage, image) = match (ancestry.first(), ancestry.last()) {
+        (Some(stage), Some(base)) => (stage, base.image),
+        _ => return Err(GenerateError::InvalidArgument(String::from("A HEALTHCHECK needs a FROM instruction"))),
+    };
+    if stage.instructions.iter().any(|instruction| matches!(instruction, Instruction::Healthcheck { .. })) {
+        return Err(GenerateError::InvalidArgument(String::from("The final stage already has a HEALTHCHECK")));
+    }
+    let inherited : Vec<&Instruction> = ancestry.iter().flat_map(|stage| stage.instructions.iter().copied()).collect();
+
+    if let Some(port) = probe.port() {
+        let exposed = inherited.iter().any(|instruction| match instruction {
+            Instruction::Expose(ports) => ports.iter().any(|exposed| is_port(exposed, port)),
+            _ => false,
+        });
+        if !exposed {
+            return Err(GenerateError::InvalidArgument(format!("The health check uses port {}, which the final stage does not EXPOSE", port)));
+        }
+    }
+
+    let tools = tools(image, &inherited);
+    let command = match &probe.check {
+        Check::Command(command) => command.clone(),
+        Check::Http { path, port } => {
+            let url = format!("http://localhost:{}/{}", port, path.trim_start_matches('/'));
+            let tool = tools.iter().find(|tool| !matches!(tool, Tool::Netcat | Tool::Bash))
+                .ok_or_else(|| missing_tool(image, "curl or wget"))?;
+            http_command(*tool, &url)
+        },
+        Check::Tcp { port } => {
+            let tool = tools.iter().find(|tool| !matches!(tool, Tool::Curl | Tool::Wget))
+                .ok_or_else(|| missing_tool(image, "netcat or bash"))?;
+            tcp_command(*tool, *port)
+        },
+    };
+
+    let mut flags = Vec::new();
+    for (name, duration) in [("interval", probe.interval), ("timeout", probe.timeout), ("start-period", probe.start_period)] {
+        if let Some(duration) = duration {
+            flags.push(Flag { name: String::from(name), value: Some(format_duration(duration)) });
+        }
+    }
+    if let Some(retries) = probe.retries {
+        flags.push(Flag { name: String::from("retries"), value: Some(retries.to_string()) });
+    }
+    Ok(Instruction::Healthcheck { flags, command: Some(Command::Shell(command)) })
+}
+
+fn missing_tool(image : &str, install : &str) -> GenerateError {
+    GenerateError::InvalidArgument(format!("{} has no tool to run the health check, install {} in the final stage", image, install))
+}
+
+fn tools(image : &str, stage : &[&Instruction]) -> Vec<Tool> {
+    let (name, tag) = packages::name_and_tag(image);
+    let (name, tag) = (name.as_str(), tag.as_str());
+    let family = PackageManager::detect(image);
+    let slim = tag.contains("slim") || family != PackageManager::Apt;
+
+    let installed = |tool : &str| stage.iter().any(|instruction| match instruction {
+        Instruction::Run { command, .. } => {
+            let command = command.to_string();
+            (command.contains("install") || command.contains("apk add")) && command.split_whitespace().any(|word| word == tool || word.starts_with(&format!("{}=", tool)))
+        },
+        _ => false,
+    });
+
+    let mut tools = Vec::new();
+    if (FULL_IMAGES.contains(&name) && !slim) || name == "curl" || installed("curl") {
+        tools.push(Tool::Curl);
+    }
+    if (FULL_IMAGES.contains(&name) && !slim) || family == PackageManager::Apk || installed("wget") {
+        tools.push(Tool::Wget);
+    }
+    if name == "python" {
+        let version = if tag.starts_with('2') { 2 } else { 3 };
+        tools.push(Tool::Python { version });
+    }
+    if name == "node" {
+        tools.push(Tool::Node);
+    }
+    match family {
+        PackageManager::Apk => tools.push(Tool::Netcat),
+        _ if !image.to_lowercase().contains("distroless") && name != "scratch" => tools.push(Tool::Bash),
+        _ => {},
+    }
+    tools
+}
+
+fn http_command(tool : Tool, url : &str) -> String {
+    match tool {
+        Tool::Curl => format!("curl -fsS -o /dev/null {} || exit 1", url),
+        Tool::Wget => format!("wget -q --spider {} || exit 1", url),
+        Tool::Python { version: 2 } => format!("python -c \"import urllib2; urllib2.urlopen('{}', timeout=5)\" || exit 1", url),
+        Tool::Python { .. } => format!("python -c \"import urllib.request; urllib.request.urlopen('{}', timeout=5)\" || exit 1", url),
+        Tool::Node => format!("node -e \"require('http').get('{}', r => process.exit(r.statusCode < 400 ? 0 : 1)).on('error', () => process.exit(1))\"", url),
+        Tool::Netcat | Tool::Bash => unreachable!("an HTTP check needs an HTTP client"),
+    }
+}
+
+fn tcp_command(tool : Tool, port : u32) -> String {
+    match tool {
+        Tool::Python { .. } => format!("python -c \"import socket; socket.create_connection(('localhost', {}), 5)\" || exit 1", port),
+        Tool::Node => format!("node -e \"require('net').connect({}, 'localhost', () => process.exit(0)).on('error', () => process.exit(1))\"", port),
+        Tool::Netcat => format!("nc -z localhost {} || exit 1", port),
+        Tool::Bash => format!("bash -c '</dev/tcp/localhost/{}' || exit 1", port),
+        Tool::Curl | Tool::Wget => unreachable!("a TCP check does not use an HTTP client"),
+    }
+}
+
+// An EXPOSE entry such as 80, 80/tcp or 8000-8010 covering the TCP port
+fn is_port(exposed : &str, port : u32) -> bool {
+    let (range, protocol) = exposed.split_once('/').unwrap_or((exposed, "tcp"));
+    if !protocol.eq_ignore_ascii_case("tcp") {
+        return false;
+    }
+    match range.split_once('-') {
+        Some((start, end)) => matches!((start.parse::<u32>(), end.parse::<u32>()), (Ok(start), Ok(end)) if start <= port && port <= end),
+        None => range.parse::<u32>() == Ok(port),
+    }
+}
+
+fn format_duration(duration : Duration) -> String {
+    if duration.subsec_millis() == 0 {
+        format!("{}s", duration.as_secs())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::*;
+    use crate::parser::parse;
+
+    fn healthcheck(source : &str, probe : &HealthProbe) -> Result<String, GenerateError> {
+        synthesize(&parse(source).unwrap(), probe).map(|instruction| instruction.to_string())
+    }
+
+    #[test]
+    fn picks_the_tool_of_the_image() {
+        let http = HealthProbe::http("/health", 80).interval(Duration::from_secs(30)).retries(3);
+        assert_eq!(healthcheck("FROM python:3.7\nEXPOSE 80\n", &http).unwrap(),
+                   "HEALTHCHECK --interval=30s --retries=3 CMD curl -fsS -o /dev/null http://localhost:80/health || exit 1");
+        assert_eq!(healthcheck("FROM python:3.7-alpine\nEXPOSE 80/tcp\n", &http).unwrap(),
+                   "HEALTHCHECK --interval=30s --retries=3 CMD wget -q --spider http://localhost:80/health || exit 1");
+        assert_eq!(healthcheck("FROM python:2.7-slim\nEXPOSE 80\n", &HealthProbe::http("/", 80)).unwrap(),
+                   "HEALTHCHECK CMD python -c \"import urllib2; urllib2.urlopen('http://localhost:80/', timeout=5)\" || exit 1");
+        assert_eq!(healthcheck("FROM debian:bookworm-slim\nRUN apt-get install -y curl\nEXPOSE 8000-8090\n", &HealthProbe::http("/", 8080)).unwrap(),
+                   "HEALTHCHECK CMD curl -fsS -o /dev/null http://localhost:8080/ || exit 1");
+
+        assert_eq!(healthcheck("FROM redis:7-alpine\nEXPOSE 6379\n", &HealthProbe::tcp(6379).timeout(Duration::from_millis(1500))).unwrap(),
+                   "HEALTHCHECK --timeout=1500ms CMD nc -z localhost 6379 || exit 1");
+        assert_eq!(healthcheck("FROM node:20 AS base\nFROM base\nEXPOSE 3000\n", &HealthProbe::tcp(3000)).unwrap(),
+                   "HEALTHCHECK CMD node -e \"require('net').connect(3000, 'localhost', () => process.exit(0)).on('error', () => process.exit(1))\"");
+    }
+
+    #[test]
+    fn validates_the_port_and_the_tool() {
+        assert!(healthcheck("FROM python:3.7\nEXPOSE 8080\n", &HealthProbe::http("/", 80)).is_err());
+        assert!(healthcheck("FROM python:3.7\nEXPOSE 80/udp\n", &HealthProbe::tcp(80)).is_err());
+        assert!(healthcheck("FROM debian:bookworm-slim\nEXPOSE 80\n", &HealthProbe::http("/", 80)).is_err());
+        assert!(healthcheck("FROM gcr.io/distroless/static\nEXPOSE 80\n", &HealthProbe::tcp(80)).is_err());
+        assert_eq!(healthcheck("FROM gcr.io/distroless/static\n", &HealthProbe::command("/app --health")).unwrap(),
+                   "HEALTHCHECK CMD /app --health");
+        assert!(healthcheck("FROM alpine\nHEALTHCHECK CMD true\n", &HealthProbe::command("/app --health")).is_err());
+    }
+
+    #[test]
+    fn inherits_from_the_base_stages() {
+        let source = "FROM debian:bookworm-slim AS base\nRUN apt-get install -y curl\nEXPOSE 80\nHEALTHCHECK NONE\n\
+                      FROM python:3.12-slim AS other\nEXPOSE 8080\n\
+                      FROM base\nCOPY . /app\n";
+        assert_eq!(healthcheck(source, &HealthProbe::http("/", 80)).unwrap(), "HEALTHCHECK CMD curl -fsS -o /dev/null http://localhost:80/ || exit 1");
+        assert!(healthcheck(source, &HealthProbe::http("/", 8080)).is_err());
+    }
+}
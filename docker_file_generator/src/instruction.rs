@@ -0,0 +1,362 @@
+use std::fmt;
+
+// Flags such as `--from=build` or `--platform=linux/amd64` in front of an instruction's arguments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flag {
+    pub name  : String,
+    pub value : Option<String>,
+}
+
+// The inline content of a `<<EOF` marker, written on the lines following the instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Heredoc {
+    pub delimiter : String,
+    // Every line of the body ends with a newline, as the build sees it
+    pub body      : String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Empty,
+    Comment(String),
+    From { flags : Vec<Flag>, image : String, alias : Option<String> },
+    Run { flags : Vec<Flag>, command : Command, heredocs : Vec<Heredoc> },
+    Cmd(Command),
+    Entrypoint(Command),
+    Label(Vec<(String, String)>),
+    Maintainer(String),
+    Expose(Vec<String>),
+    Env(Vec<(String, String)>),
+    Add { flags : Vec<Flag>, sources : Vec<String>, destination : String, heredocs : Vec<Heredoc> },
+    Copy { flags : Vec<Flag>, sources : Vec<String>, destination : String, heredocs : Vec<Heredoc> },
+    Volume(Vec<String>),
+    User(String),
+    WorkDir(String),
+    Arg { name : String, default : Option<String> },
+    OnBuild(Box<Instruction>),
+    StopSignal(String),
+    Healthcheck { flags : Vec<Flag>, command : Option<Command> },
+    Shell(Vec<String>),
+    // A line pushed as is, which could not be understood as an instruction
+    Raw(String),
+}
+
+impl Flag {
+    pub fn new(name : &str, value : &str) -> Flag {
+        Flag { name: name.to_string(), value: Some(value.to_string()) }
+    }
+}
+
+impl Heredoc {
+    // Pick a delimiter which no line of the body could be mistaken for
+    pub fn new(body : &str) -> Heredoc {
+        let mut body = body.to_string();
+        if !body.is_empty() && !body.ends_with('\n') {
+            body.push('\n');
+        }
+
+        let mut delimiter = String::from("EOF");
+        let mut suffix = 0;
+        while body.lines().any(|line| line.trim_start_matches('\t') == delimiter) {
+            suffix += 1;
+            delimiter = format!("EOF{}", suffix);
+        }
+        Heredoc { delimiter, body }
+    }
+
+    // The marker referring to the heredoc. It is quoted, so the build does not expand variables in the body.
+    pub fn marker(&self) -> String {
+        format!("<<\"{}\"", self.delimiter)
+    }
+}
+
+impl Instruction {
+    pub fn keyword(&self) -> Option<&'static str> {
+        let keyword = match self {
+            Instruction::Empty | Instruction::Comment(_) | Instruction::Raw(_) => return None,
+            Instruction::From { .. } => "FROM",
+            Instruction::Run { .. } => "RUN",
+            Instruction::Cmd(_) => "CMD",
+            Instruction::Entrypoint(_) => "ENTRYPOINT",
+            Instruction::Label(_) => "LABEL",
+            Instruction::Maintainer(_) => "MAINTAINER",
+            Instruction::Expose(_) => "EXPOSE",
+            Instruction::Env(_) => "ENV",
+            Instruction::Add { .. } => "ADD",
+            Instruction::Copy { .. } => "COPY",
+            Instruction::Volume(_) => "VOLUME",
+            Instruction::User(_) => "USER",
+            Instruction::WorkDir(_) => "WORKDIR",
+            Instruction::Arg { .. } => "ARG",
+            Instruction::OnBuild(_) => "ONBUILD",
+            Instruction::StopSignal(_) => "STOPSIGNAL",
+            Instruction::Healthcheck { .. } => "HEALTHCHECK",
+            Instruction::Shell(_) => "SHELL",
+        };
+        Some(keyword)
+    }
+
+    pub fn flags(&self) -> &[Flag] {
+        match self {
+            Instruction::From { flags, .. } | Instruction::Run { flags, .. } | Instruction::Add { flags, .. }
+            | Instruction::Copy { flags, .. } | Instruction::Healthcheck { flags, .. } => flags,
+            _ => &[],
+        }
+    }
+
+    pub fn flag(&self, name : &str) -> Option<&Flag> {
+        self.flags().iter().find(|flag| flag.name == name)
+    }
+
+    pub fn heredocs(&self) -> &[Heredoc] {
+        match self {
+            Instruction::Run { heredocs, .. } | Instruction::Add { heredocs, .. } | Instruction::Copy { heredocs, .. } => heredocs,
+            _ => &[],
+        }
+    }
+}
+
+// A build stage: its FROM instruction and everything following it, up to the next FROM
+#[derive(Debug)]
+pub struct Stage<'a> {
+    pub index        : usize,
+    pub image        : &'a str,
+    pub alias        : Option<&'a str>,
+    pub instructions : Vec<&'a Instruction>,
+}
+
+impl<'a> Stage<'a> {
+    // The instructions which are build steps, without comments and empty lines
+    pub fn steps(&self) -> impl Iterator<Item = &'a Instruction> + '_ {
+        self.instructions.iter().cloned().filter(|instruction| instruction.keyword().is_some())
+    }
+
+    pub fn name(&self) -> String {
+        match self.alias {
+            Some(alias) => alias.to_string(),
+            None => self.index.to_string(),
+        }
+    }
+}
+
+// Split instructions into build stages. Instructions before the first FROM (global ARGs) belong to no stage.
+pub fn stages(instructions : &[Instruction]) -> Vec<Stage<'_>> {
+    let mut stages : Vec<Stage> = Vec::new();
+    for instruction in instructions {
+        match instruction {
+            Instruction::From { image, alias, .. } => stages.push(Stage {
+                index: stages.len(),
+                image,
+                alias: alias.as_deref(),
+                instructions: Vec::new(),
+            }),
+            _ => if let Some(stage) = stages.last_mut() {
+                stage.instructions.push(instruction);
+            },
+        }
+    }
+    stages
+}
+
+// The last stage, then the earlier stages it is built on, following the aliases their FROM refer to
+pub fn ancestry<'a, 'b>(stages : &'b [Stage<'a>]) -> Vec<&'b Stage<'a>> {
+    let mut ancestry : Vec<&Stage> = stages.last().into_iter().collect();
+    while let Some(base) = ancestry.last().and_then(|stage| stages[..stage.index].iter().rev().find(|other| other.alias == Some(stage.image))) {
+        ancestry.push(base);
+    }
+    ancestry
+}
+
+// The image the last stage is built on, following the aliases of the previous stages
+pub fn base_image(instructions : &[Instruction]) -> Option<&str> {
+    ancestry(&stages(instructions)).last().map(|stage| stage.image)
+}
+
+// The line each instruction starts at once rendered, the bodies of heredocs taking lines of their own
+pub fn rendered_lines(instructions : &[Instruction]) -> Vec<usize> {
+    let mut next = 1;
+    instructions.iter().map(|instruction| {
+        let line = next;
+        next += instruction.to_string().matches('\n').count() + 1;
+        line
+    }).collect()
+}
+
+// Render arguments as a JSON array, as expected by the exec form of RUN and CMD
+pub fn exec_form<S : AsRef<str>>(args : &[S]) -> String {
+    let quoted : Vec<String> = args.iter().map(|arg| {
+        let mut quoted = String::from("\"");
+        for c in arg.as_ref().chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+                _ => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }).collect();
+
+    let mut line = String::from("[");
+    line.push_str(&quoted.join(", "));
+    line.push(']');
+    line
+}
+
+// Quote a value for ENV, LABEL and ARG, only when it can not be written as a bare word.
+// An escaped `$` stays escaped, so the build does not expand it.
+pub fn quote(value : &str) -> String {
+    let characters = unescape(value);
+    let bare = !characters.is_empty() && !characters.iter().any(|&(c, _)| c.is_whitespace() || c == '"' || c == '\'' || c == '\\');
+    let mut quoted = String::new();
+    for &(c, literal) in &characters {
+        if (c == '$' && literal) || (!bare && (c == '"' || c == '\\')) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    if bare { quoted } else { format!("\"{}\"", quoted) }
+}
+
+// The characters of a value of ENV, LABEL or ARG, each `$` telling whether it is escaped, and so literal.
+// The backslashes before a `$` pair up the way the shell reads them, the others are literal.
+pub(crate) fn unescape(value : &str) -> Vec<(char, bool)> {
+    let mut characters = Vec::new();
+    let mut backslashes = 0;
+    for c in value.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let literal = c == '$' && backslashes % 2 == 1;
+        let kept = if c == '$' { backslashes / 2 } else { backslashes };
+        characters.extend(std::iter::repeat_n(('\\', false), kept));
+        characters.push((c, literal));
+        backslashes = 0;
+    }
+    characters.extend(std::iter::repeat_n(('\\', false), backslashes));
+    characters
+}
+
+// The reverse of unescape
+pub(crate) fn escape(characters : &[(char, bool)]) -> String {
+    let mut value = String::new();
+    let mut backslashes = 0;
+    for &(c, literal) in characters {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let run = if c == '$' { 2 * backslashes + literal as usize } else { backslashes };
+        value.extend(std::iter::repeat_n('\\', run));
+        value.push(c);
+        backslashes = 0;
+    }
+    value.extend(std::iter::repeat_n('\\', backslashes));
+    value
+}
+
+// Paths are written as plain words, unless one of them needs the JSON form.
+// A trailing backslash would continue the line.
+fn paths<S : AsRef<str>>(paths : &[S]) -> String {
+    let json = paths.iter().any(|path| {
+        let path = path.as_ref();
+        path.is_empty() || path.starts_with('[') || path.ends_with('\\') || path.chars().any(char::is_whitespace)
+    });
+
+    if json {
+        exec_form(paths)
+    } else {
+        paths.iter().map(|path| path.as_ref()).collect::<Vec<&str>>().join(" ")
+    }
+}
+
+fn key_values(pairs : &[(String, String)]) -> String {
+    pairs.iter()
+        .map(|(key, value)| format!("{}={}", key, quote(value)))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(ref value) => write!(f, "--{}={}", self.name, value),
+            None => write!(f, "--{}", self.name),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Shell(line) => f.write_str(line),
+            Command::Exec(args) => f.write_str(&exec_form(args)),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let keyword = match self.keyword() {
+            Some(keyword) => keyword,
+            None => return match self {
+                Instruction::Comment(line) if line.is_empty() => f.write_str("#"),
+                Instruction::Comment(line) => write!(f, "# {}", line),
+                Instruction::Raw(line) => f.write_str(line),
+                _ => Ok(()),
+            },
+        };
+
+        f.write_str(keyword)?;
+        for flag in self.flags() {
+            write!(f, " {}", flag)?;
+        }
+
+        match self {
+            Instruction::From { image, alias, .. } => {
+                write!(f, " {}", image)?;
+                if let Some(alias) = alias {
+                    write!(f, " AS {}", alias)?;
+                }
+                Ok(())
+            },
+            Instruction::Run { command, .. } | Instruction::Cmd(command) | Instruction::Entrypoint(command) => write!(f, " {}", command),
+            Instruction::Label(pairs) | Instruction::Env(pairs) => write!(f, " {}", key_values(pairs)),
+            Instruction::Expose(ports) => write!(f, " {}", ports.join(" ")),
+            Instruction::Add { sources, destination, .. } | Instruction::Copy { sources, destination, .. } => {
+                let mut all = sources.clone();
+                all.push(destination.clone());
+                write!(f, " {}", paths(&all))
+            },
+            Instruction::Volume(volumes) => write!(f, " {}", paths(volumes)),
+            Instruction::Maintainer(value) | Instruction::User(value) | Instruction::WorkDir(value) | Instruction::StopSignal(value) => write!(f, " {}", value),
+            Instruction::Arg { name, default } => match default {
+                Some(default) => write!(f, " {}={}", name, quote(default)),
+                None => write!(f, " {}", name),
+            },
+            Instruction::OnBuild(instruction) => write!(f, " {}", instruction),
+            Instruction::Healthcheck { command, .. } => match command {
+                Some(command) => write!(f, " CMD {}", command),
+                None => f.write_str(" NONE"),
+            },
+            Instruction::Shell(args) => write!(f, " {}", exec_form(args)),
+            Instruction::Empty | Instruction::Comment(_) | Instruction::Raw(_) => Ok(()),
+        }?;
+
+        for heredoc in self.heredocs() {
+            write!(f, "\n{}{}", heredoc.body, heredoc.delimiter)?;
+        }
+        Ok(())
+    }
+}
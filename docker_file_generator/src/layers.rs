@@ -0,0 +1,358 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::instruction::{self, Instruction};
+
+// Manifests which are usually copied on their own, so installing dependencies does not depend on the sources
+pub(crate) const MANIFESTS : [&str; 12] = ["requirements.txt", "Pipfile", "Pipfile.lock", "pyproject.toml", "poetry.lock", "package.json",
+                                           "package-lock.json", "yarn.lock", "Cargo.toml", "Cargo.lock", "go.mod", "go.sum"];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerKind {
+    // RUN, COPY and ADD add a filesystem layer
+    Filesystem,
+    // Everything else only changes the image configuration, but still has its own cache entry
+    Metadata,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Layer {
+    pub stage       : String,
+    // Index of the instruction, its line in the Dockerfile, and the instruction as written
+    pub index       : usize,
+    pub line        : usize,
+    pub instruction : String,
+    pub kind        : LayerKind,
+    // The files of the build context the layer's cache key depends on, relative to the context
+    pub inputs      : Vec<String>,
+}
+
+// The first invalidated layer of a stage, and why
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Invalidation {
+    pub stage       : String,
+    pub line        : usize,
+    pub instruction : String,
+    // The changed files the layer depends on, empty when an earlier stage it uses was invalidated
+    pub causes      : Vec<String>,
+    // How many layers of the stage are rebuilt
+    pub rebuilt     : usize,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Report {
+    pub invalidations : Vec<Invalidation>,
+    pub suggestions   : Vec<String>,
+}
+
+// Every instruction of every stage as a potential layer, with the context files COPY and ADD read
+pub fn layers(instructions : &[Instruction], context : &Path) -> io::Result<Vec<Layer>> {
+    layers_at(instructions, &instruction::rendered_lines(instructions), context)
+}
+
+fn layers_at(instructions : &[Instruction], lines : &[usize], context : &Path) -> io::Result<Vec<Layer>> {
+    let files = context_files(context)?;
+    let index = |instruction : &Instruction| instructions.iter().position(|other| std::ptr::eq(other, instruction)).unwrap();
+
+    let mut layers = Vec::new();
+    for stage in instruction::stages(instructions) {
+        let from = instructions.iter().enumerate()
+            .filter(|(_, instruction)| matches!(instruction, Instruction::From { .. }))
+            .nth(stage.index)
+            .map(|(position, _)| position)
+            .unwrap();
+        layers.push(Layer { stage: stage.name(), index: from, line: lines[from], instruction: instructions[from].to_string(), kind: LayerKind::Metadata, inputs: Vec::new() });
+
+        for step in stage.steps() {
+            let kind = match step {
+                Instruction::Run { .. } | Instruction::Copy { .. } | Instruction::Add { .. } => LayerKind::Filesystem,
+                _ => LayerKind::Metadata,
+            };
+            layers.push(Layer { stage: stage.name(), index: index(step), line: lines[index(step)], instruction: step.to_string(), kind, inputs: inputs(step, &files) });
+        }
+    }
+    Ok(layers)
+}
+
+// Given the files changed in the context, find the first layer of every stage whose cache no longer applies
+pub fn analyze(instructions : &[Instruction], context : &Path, changed : &[&str]) -> io::Result<Report> {
+    analyze_at(instructions, &instruction::rendered_lines(instructions), context, changed)
+}
+
+// Analyze the instructions as parsed with their lines, reporting the lines as written in the Dockerfile
+pub fn analyze_with_lines(parsed : &[(usize, Instruction)], context : &Path, changed : &[&str]) -> io::Result<Report> {
+    let (lines, instructions) : (Vec<usize>, Vec<Instruction>) = parsed.iter().cloned().unzip();
+    analyze_at(&instructions, &lines, context, changed)
+}
+
+fn analyze_at(instructions : &[Instruction], lines : &[usize], context : &Path, changed : &[&str]) -> io::Result<Report> {
+    let layers = layers_at(instructions, lines, context)?;
+    let changed : BTreeSet<String> = changed.iter().map(|path| normalize(path)).collect();
+
+    // Stages are listed in order and only use earlier ones, so a single pass is enough
+    let mut report = Report::default();
+    let mut invalidated : BTreeSet<String> = BTreeSet::new();
+    let stages = instruction::stages(instructions);
+    for stage in &stages {
+        let stage_layers : Vec<&Layer> = layers.iter().filter(|layer| layer.stage == stage.name()).collect();
+        // A stage is named by its alias, but can also be referred to by its index
+        let uses_invalidated = |instruction : &Instruction| match instruction {
+            Instruction::From { image, .. } => invalidated.contains(image.as_str()),
+            Instruction::Copy { .. } => instruction.flag("from")
+                .and_then(|flag| flag.value.as_ref())
+                .is_some_and(|from| invalidated.contains(from)
+                             || stages.iter().any(|other| other.index.to_string() == *from && invalidated.contains(&other.name()))),
+            _ => false,
+        };
+
+        let steps : Vec<&Instruction> = std::iter::once(&instructions[stage_layers[0].index]).chain(stage.steps()).collect();
+        let first = stage_layers.iter().zip(&steps).position(|(layer, step)| {
+            layer.inputs.iter().any(|input| changed.contains(input)) || uses_invalidated(step)
+        });
+
+        if let Some(first) = first {
+            let layer = stage_layers[first];
+            invalidated.insert(stage.name());
+            report.invalidations.push(Invalidation {
+                stage: stage.name(),
+                line: layer.line,
+                instruction: layer.instruction.clone(),
+                causes: layer.inputs.iter().filter(|input| changed.contains(*input)).cloned().collect(),
+                rebuilt: stage_layers.len() - first,
+            });
+            report.suggestions.extend(suggest(&stage_layers[first..], &steps[first..], &changed));
+        }
+    }
+    Ok(report)
+}
+
+// A RUN after the invalidated copy which only needs a manifest could run before it, on the manifest alone
+fn suggest(layers : &[&Layer], steps : &[&Instruction], changed : &BTreeSet<String>) -> Vec<String> {
+    let copy = layers[0];
+    let mut suggestions = Vec::new();
+    for (layer, step) in layers.iter().zip(steps).skip(1) {
+        let command = match step {
+            Instruction::Run { command, .. } => command.to_string(),
+            _ => continue,
+        };
+        let needed : Vec<&String> = copy.inputs.iter()
+            .filter(|input| !changed.contains(*input))
+            .filter(|input| {
+                let name = input.rsplit('/').next().unwrap_or(input);
+                MANIFESTS.contains(&name) && command.split_whitespace().any(|word| word.trim_end_matches(';') == name || word.ends_with(&format!("/{}", name)))
+            })
+            .collect();
+        if !needed.is_empty() {
+            let needed : Vec<&str> = needed.iter().map(|input| input.as_str()).collect();
+            suggestions.push(format!("line {}: `{}` only needs {}; copy {} on its own and run it before line {}, so changes to other files keep its cache",
+                                     layer.line, layer.instruction, needed.join(", "), if needed.len() == 1 { "it" } else { "them" }, copy.line));
+        }
+    }
+    suggestions
+}
+
+// The files of the context a COPY or ADD reads, a copy from another stage or image reads none
+fn inputs(instruction : &Instruction, files : &[String]) -> Vec<String> {
+    let sources = match instruction {
+        Instruction::Copy { sources, heredocs, .. } | Instruction::Add { sources, heredocs, .. }
+            if instruction.flag("from").is_none() && heredocs.is_empty() => sources,
+        _ => return Vec::new(),
+    };
+
+    files.iter()
+        .filter(|file| sources.iter().filter(|source| !source.contains("://")).any(|source| source_matches(source, file)))
+        .cloned()
+        .collect()
+}
+
+fn source_matches(source : &str, file : &str) -> bool {
+    let source = normalize(source);
+    if source.is_empty() {
+        return true;
+    }
+    // A source matching a directory copies everything below it
+    let mut prefix = String::new();
+    for component in file.split('/') {
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        prefix.push_str(component);
+        if glob(&source, &prefix) {
+            return true;
+        }
+    }
+    false
+}
+
+fn normalize(path : &str) -> String {
+    let path = path.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/');
+    if path == "." { String::new() } else { path.to_string() }
+}
+
+// Every file of the context, relative to it, without the ones excluded by .dockerignore
+fn context_files(context : &Path) -> io::Result<Vec<String>> {
+    let ignore = match fs::read_to_string(context.join(".dockerignore")) {
+        Ok(content) => content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect(),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(error),
+    };
+
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(context.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else if !ignored(&ignore, &path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// The last matching pattern wins, and a pattern starting with ! includes the file again
+fn ignored(patterns : &[String], path : &str) -> bool {
+    let mut ignored = false;
+    for pattern in patterns {
+        let (exclude, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (false, pattern),
+            None => (true, pattern.as_str()),
+        };
+        if source_matches(pattern, path) {
+            ignored = exclude;
+        }
+    }
+    ignored
+}
+
+// Match a path against a pattern where * matches within a path component, ** across them and ? a single character
+fn glob(pattern : &str, path : &str) -> bool {
+    let pattern : Vec<char> = pattern.chars().collect();
+    let path : Vec<char> = path.chars().collect();
+
+    fn matches(pattern : &[char], path : &[char]) -> bool {
+        match pattern.first() {
+            None => path.is_empty(),
+            Some('*') if pattern.get(1) == Some(&'*') => {
+                let rest = if pattern.get(2) == Some(&'/') { &pattern[3..] } else { &pattern[2..] };
+                (0..=path.len()).any(|skip| matches(rest, &path[skip..]))
+            },
+            Some('*') => (0..=path.len())
+                .take_while(|skip| *skip == 0 || path[skip - 1] != '/')
+                .any(|skip| matches(&pattern[1..], &path[skip..])),
+            Some('?') => !path.is_empty() && path[0] != '/' && matches(&pattern[1..], &path[1..]),
+            Some(c) => path.first() == Some(c) && matches(&pattern[1..], &path[1..]),
+        }
+    }
+    matches(&pattern, &path)
+}
+
+impl Report {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a report is always serializable")
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if self.invalidations.is_empty() {
+            return writeln!(f, "No layer is invalidated");
+        }
+        for invalidation in &self.invalidations {
+            write!(f, "stage {}: line {} `{}` is invalidated", invalidation.stage, invalidation.line, invalidation.instruction)?;
+            if invalidation.causes.is_empty() {
+                write!(f, " by an earlier stage")?;
+            } else {
+                write!(f, " by {}", invalidation.causes.join(", "))?;
+            }
+            writeln!(f, ", {} layers are rebuilt", invalidation.rebuilt)?;
+        }
+        for suggestion in &self.suggestions {
+            writeln!(f, "suggestion: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layers::*;
+    use crate::parser::{parse, parse_with_lines};
+
+    fn context() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in &[("app.py", "print('hi')"), ("requirements.txt", "flask"), ("README.md", "# app"),
+                                 (".dockerignore", "*.md\n"), ("static/css/site.css", "")] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn models_inputs_of_copies() {
+        let context = context();
+        let instructions = parse("FROM python:3.7-slim\nCOPY requirements.txt /app/\nCOPY static/**/*.css /app/\nENV NAME=World\n").unwrap();
+
+        let layers = layers(&instructions, context.path()).unwrap();
+        assert_eq!(layers.len(), 4);
+        assert_eq!(layers[1].inputs, vec!["requirements.txt"]);
+        assert_eq!(layers[2].inputs, vec!["static/css/site.css"]);
+        assert_eq!(layers[3].kind, LayerKind::Metadata);
+        assert!(glob("static/**/*.css", "static/site.css"));
+    }
+
+    #[test]
+    fn reports_first_invalidated_layer_and_suggests_reordering() {
+        let context = context();
+        let instructions = parse("FROM python:3.7-slim AS build\n\
+                                  WORKDIR /app\n\
+                                  COPY . /app\n\
+                                  RUN pip install -r requirements.txt\n\
+                                  FROM build\n\
+                                  CMD [\"python\", \"app.py\"]\n").unwrap();
+
+        let report = analyze(&instructions, context.path(), &["app.py"]).unwrap();
+        assert_eq!(report.invalidations.len(), 2);
+        assert_eq!((report.invalidations[0].line, report.invalidations[0].rebuilt), (3, 2));
+        assert_eq!(report.invalidations[0].causes, vec!["app.py"]);
+        assert_eq!((report.invalidations[1].line, report.invalidations[1].causes.len()), (5, 0));
+        assert_eq!(report.suggestions, vec!["line 4: `RUN pip install -r requirements.txt` only needs requirements.txt; \
+                                             copy it on its own and run it before line 3, so changes to other files keep its cache"]);
+
+        assert!(analyze(&instructions, context.path(), &["README.md"]).unwrap().invalidations.is_empty());
+    }
+
+    #[test]
+    fn reports_the_lines_of_the_source() {
+        let context = context();
+        let parsed = parse_with_lines("# syntax=docker/dockerfile:1\n\
+                                       FROM python:3.7-slim\n\n\
+                                       COPY . \\\n  /app\n\
+                                       RUN pip install -r requirements.txt\n").unwrap();
+
+        let report = analyze_with_lines(&parsed, context.path(), &["app.py"]).unwrap();
+        assert_eq!(report.invalidations[0].line, 4);
+        assert_eq!(report.suggestions, vec!["line 6: `RUN pip install -r requirements.txt` only needs requirements.txt; \
+                                             copy it on its own and run it before line 4, so changes to other files keep its cache"]);
+
+        let instructions = parse("FROM python:3.7-slim\nRUN <<EOF\npip install -U pip\nEOF\nCOPY . /app\n").unwrap();
+        assert_eq!(analyze(&instructions, context.path(), &["app.py"]).unwrap().invalidations[0].line, 5);
+    }
+}
@@ -1,2 +1,68 @@
+// failure's derive expands its impls inside an anonymous const
+#![allow(non_local_definitions)]
+
+// Lets the paths emitted by dockerfile! resolve inside this crate as well
+extern crate self as dock_gen;
+
 pub mod generator;
+pub mod builder;
+pub mod instruction;
+pub mod parser;
+pub mod formatter;
+pub mod diff;
+pub mod oci;
+pub mod testing;
+pub mod security;
+pub mod compat;
+pub mod matrix;
+pub mod rust_project;
+pub mod visit;
+pub mod policy;
+pub mod layers;
+pub mod codegen;
+pub mod dev;
+pub mod health;
+pub mod packages;
+pub mod provenance;
+pub mod watch;
+pub mod size;
 
+/// Build a `DockerfileGenerator` from an inline Dockerfile, checked at compile time.
+///
+/// ```
+/// use dock_gen::dockerfile;
+///
+/// let version = "3.7";
+/// let generator = dockerfile! {
+///     ## "Use an official Python runtime as a parent image";
+///     FROM format!("python:{}-slim", version);
+///     WORKDIR "/app";
+///     COPY "." "/app";
+///     EXPOSE 80;
+///     ENV NAME = "World";
+///     CMD ["python", "app.py"];
+/// };
+/// ```
+///
+/// Unknown instructions are rejected:
+///
+/// ```compile_fail
+/// let generator = dock_gen::dockerfile! { FORM "python:3.7-slim"; };
+/// ```
+///
+/// And so are the few the macro does not support:
+///
+/// ```compile_fail
+/// let generator = dock_gen::dockerfile! { ONBUILD "RUN make"; };
+/// ```
+///
+/// So are malformed exec arrays:
+///
+/// ```compile_fail
+/// let generator = dock_gen::dockerfile! { CMD ["python" "app.py"]; };
+/// ```
+///
+/// ```compile_fail
+/// let generator = dock_gen::dockerfile! { CMD ["python", 3]; };
+/// ```
+pub use dock_gen_macros::dockerfile;
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::generator::{DockerfileGenerator, GenerateError};
+
+// Build arguments, each with the values to generate a Dockerfile for
+#[derive(Clone, Debug, Default)]
+pub struct Matrix {
+    axes : Vec<(String, Vec<String>)>,
+}
+
+// One value of every axis
+#[derive(Clone, Debug, PartialEq)]
+pub struct Combination {
+    values : Vec<(String, String)>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Output {
+    pub path : PathBuf,
+    pub args : BTreeMap<String, String>,
+}
+
+// Every Dockerfile generated from a matrix, in the order of the combinations
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Manifest {
+    pub outputs : Vec<Output>,
+}
+
+impl Matrix {
+    pub fn axis<S : AsRef<str>>(&mut self, name : &str, values : &[S]) -> &mut Matrix {
+        self.axes.push((name.to_string(), values.iter().map(|value| value.as_ref().to_string()).collect()));
+        self
+    }
+
+    // The cartesian product of the axes. The first axis changes the slowest.
+    pub fn combinations(&self) -> Vec<Combination> {
+        let mut combinations = vec![Combination { values: Vec::new() }];
+        for (name, values) in &self.axes {
+            combinations = combinations.iter()
+                .flat_map(|combination| values.iter().map(move |value| {
+                    let mut values = combination.values.clone();
+                    values.push((name.clone(), value.clone()));
+                    Combination { values }
+                }))
+                .collect();
+        }
+        combinations
+    }
+
+    // Build a Dockerfile for every combination, and write it to the template with `{axis}` replaced by the values
+    pub fn generate<F, G>(&self, template : &str, mut f : F) -> Result<Manifest, GenerateError>
+        where F: FnMut(&Combination) -> G,
+              G: Into<DockerfileGenerator> {
+        let mut manifest = Manifest::default();
+        let mut seen = BTreeSet::new();
+
+        for combination in self.combinations() {
+            let path = PathBuf::from(combination.expand(template)?);
+            if !seen.insert(path.clone()) {
+                return Err(GenerateError::InvalidArgument(format!("{} is generated by more than one combination", path.display())));
+            }
+
+            if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent).map_err(GenerateError::IO)?;
+            }
+            let mut generator : DockerfileGenerator = f(&combination).into();
+            generator.path(path.clone()).generate()?;
+            manifest.outputs.push(Output { path, args: combination.values.into_iter().collect() });
+        }
+        Ok(manifest)
+    }
+}
+
+impl Combination {
+    pub fn get(&self, name : &str) -> Option<&str> {
+        self.values.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    // The value of an axis the matrix is known to have
+    pub fn value(&self, name : &str) -> &str {
+        self.get(name).unwrap_or_else(|| panic!("the matrix has no axis {}", name))
+    }
+
+    pub fn values(&self) -> &[(String, String)] {
+        &self.values
+    }
+
+    // Replace every `{axis}` of the template by its value
+    pub fn expand(&self, template : &str) -> Result<String, GenerateError> {
+        let mut expanded = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            let end = rest[start..].find('}')
+                .ok_or_else(|| GenerateError::InvalidArgument(format!("Unclosed placeholder in {}", template)))?;
+            let name = &rest[start + 1..start + end];
+            let value = self.get(name)
+                .ok_or_else(|| GenerateError::InvalidArgument(format!("Unknown axis {} in {}", name, template)))?;
+            expanded.push_str(value);
+            rest = &rest[start + end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a manifest is always serializable")
+    }
+
+    pub fn write(&self, path : &Path) -> Result<(), GenerateError> {
+        fs::write(path, self.to_json()).map_err(GenerateError::IO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::*;
+
+    #[test]
+    fn expands_every_combination() {
+        let mut matrix = Matrix::default();
+        matrix.axis("python", &["2.7", "3.7", "3.11"])
+            .axis("variant", &["slim", "alpine"]);
+
+        let names : Vec<String> = matrix.combinations().iter()
+            .map(|combination| combination.expand("Dockerfile.{python}-{variant}").unwrap())
+            .collect();
+        assert_eq!(names, vec!["Dockerfile.2.7-slim", "Dockerfile.2.7-alpine", "Dockerfile.3.7-slim",
+                               "Dockerfile.3.7-alpine", "Dockerfile.3.11-slim", "Dockerfile.3.11-alpine"]);
+        assert!(matrix.combinations()[0].expand("Dockerfile.{arch}").is_err());
+    }
+
+    #[test]
+    fn writes_outputs_and_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("Dockerfile.{python}-{variant}");
+
+        let mut matrix = Matrix::default();
+        matrix.axis("python", &["3.7", "3.11"]).axis("variant", &["slim"]);
+        let manifest = matrix.generate(template.to_str().unwrap(), |combination| {
+            DockerfileGenerator::builder().from(&format!("python:{}-{}", combination.value("python"), combination.value("variant")))
+        }).unwrap();
+
+        assert_eq!(manifest.outputs.len(), 2);
+        assert_eq!(manifest.outputs[1].path, dir.path().join("Dockerfile.3.11-slim"));
+        assert_eq!(manifest.outputs[1].args["python"], "3.11");
+        assert_eq!(fs::read_to_string(&manifest.outputs[1].path).unwrap(), "FROM python:3.11-slim\r\n");
+
+        let per_version = matrix.generate(dir.path().join("Dockerfile.{python}").to_str().unwrap(), |_| DockerfileGenerator::default());
+        assert!(per_version.is_ok());
+        matrix.axis("arch", &["amd64", "arm64"]);
+        let duplicate = matrix.generate(dir.path().join("Dockerfile.{python}").to_str().unwrap(), |_| DockerfileGenerator::default());
+        assert!(duplicate.is_err());
+    }
+}
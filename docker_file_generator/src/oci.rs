@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::instruction::{self, Command, Instruction};
+
+// Serialized as `{}`, the value type of ExposedPorts and Volumes in the OCI schema
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Empty {}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Healthcheck {
+    pub test         : Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval     : Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout      : Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_period : Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries      : Option<u64>,
+}
+
+// The `config` object of an OCI image configuration, as the Dockerfile would produce it.
+// The configuration of external base images is unknown, so only what the Dockerfile sets is reported.
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageConfig {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub user          : String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub exposed_ports : BTreeMap<String, Empty>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env           : Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrypoint    : Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmd           : Option<Vec<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub volumes       : BTreeMap<String, Empty>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub working_dir   : String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels        : BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stop_signal   : String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthcheck   : Option<Healthcheck>,
+    #[serde(skip)]
+    shell             : Vec<String>,
+}
+
+impl ImageConfig {
+    pub fn env_value(&self, key : &str) -> Option<&str> {
+        let prefix = format!("{}=", key);
+        self.env.iter().find(|entry| entry.starts_with(&prefix)).map(|entry| &entry[prefix.len()..])
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("an image config is always serializable")
+    }
+
+    fn set_env(&mut self, key : &str, value : &str) {
+        let entry = format!("{}={}", key, value);
+        let prefix = format!("{}=", key);
+        match self.env.iter_mut().find(|entry| entry.starts_with(&prefix)) {
+            Some(existing) => *existing = entry,
+            None => self.env.push(entry),
+        }
+    }
+
+    fn command(&self, command : &Command) -> Vec<String> {
+        match command {
+            Command::Exec(args) => args.clone(),
+            Command::Shell(line) => {
+                let mut args = if self.shell.is_empty() {
+                    vec![String::from("/bin/sh"), String::from("-c")]
+                } else {
+                    self.shell.clone()
+                };
+                args.push(line.clone());
+                args
+            },
+        }
+    }
+}
+
+// Evaluate the instructions of the final stage, with Docker's override semantics.
+// A stage based on an earlier stage starts from that stage's configuration.
+pub fn preview(instructions : &[Instruction]) -> ImageConfig {
+    let mut configs : Vec<ImageConfig> = Vec::new();
+    let stages = instruction::stages(instructions);
+
+    for stage in &stages {
+        let base = stages[..stage.index].iter()
+            .find(|other| other.alias == Some(stage.image) || other.index.to_string() == stage.image)
+            .map(|other| configs[other.index].clone());
+        let mut config = base.unwrap_or_default();
+        let mut args : BTreeMap<String, String> = BTreeMap::new();
+        let mut cmd_set = false;
+
+        for step in stage.steps() {
+            evaluate(&mut config, &mut args, &mut cmd_set, step);
+        }
+        configs.push(config);
+    }
+
+    configs.pop().unwrap_or_default()
+}
+
+// cmd_set tells whether a CMD came earlier in the stage, which an ENTRYPOINT then keeps
+fn evaluate(config : &mut ImageConfig, args : &mut BTreeMap<String, String>, cmd_set : &mut bool, step : &Instruction) {
+    let vars = |config : &ImageConfig, args : &BTreeMap<String, String>| {
+        let mut vars = args.clone();
+        for entry in &config.env {
+            if let Some(index) = entry.find('=') {
+                vars.insert(entry[..index].to_string(), entry[index + 1..].to_string());
+            }
+        }
+        vars
+    };
+
+    match step {
+        Instruction::Arg { name, default } => {
+            let value = default.as_ref().map(|value| expand(value, &vars(config, args))).unwrap_or_default();
+            args.insert(name.clone(), value);
+        },
+        Instruction::Env(pairs) => {
+            // All the pairs of a single ENV are expanded with the variables defined before it
+            let current = vars(config, args);
+            for (key, value) in pairs {
+                config.set_env(key, &expand(value, &current));
+            }
+        },
+        Instruction::Label(pairs) => {
+            let current = vars(config, args);
+            for (key, value) in pairs {
+                config.labels.insert(key.clone(), expand(value, &current));
+            }
+        },
+        Instruction::Expose(ports) => {
+            let current = vars(config, args);
+            for port in ports {
+                let port = expand(port, &current);
+                let port = if port.contains('/') { port } else { format!("{}/tcp", port) };
+                config.exposed_ports.insert(port, Empty {});
+            }
+        },
+        Instruction::Volume(volumes) => {
+            let current = vars(config, args);
+            for volume in volumes {
+                config.volumes.insert(expand(volume, &current), Empty {});
+            }
+        },
+        Instruction::WorkDir(dir) => {
+            let dir = expand(dir, &vars(config, args));
+            config.working_dir = if dir.starts_with('/') || config.working_dir.is_empty() {
+                dir
+            } else {
+                format!("{}/{}", config.working_dir.trim_end_matches('/'), dir)
+            };
+        },
+        Instruction::User(user) => config.user = expand(user, &vars(config, args)),
+        Instruction::StopSignal(signal) => config.stop_signal = expand(signal, &vars(config, args)),
+        Instruction::Shell(shell) => config.shell = shell.clone(),
+        // Setting the entrypoint resets the command inherited from the base image, not one set in the stage
+        Instruction::Entrypoint(command) => {
+            config.entrypoint = Some(config.command(command));
+            if !*cmd_set {
+                config.cmd = None;
+            }
+        },
+        Instruction::Cmd(command) => {
+            config.cmd = Some(config.command(command));
+            *cmd_set = true;
+        },
+        Instruction::Healthcheck { flags, command } => {
+            let test = match command {
+                None => vec![String::from("NONE")],
+                Some(Command::Exec(args)) => {
+                    let mut test = vec![String::from("CMD")];
+                    test.extend(args.iter().cloned());
+                    test
+                },
+                Some(Command::Shell(line)) => vec![String::from("CMD-SHELL"), line.clone()],
+            };
+
+            let flag = |name : &str| flags.iter().find(|flag| flag.name == name).and_then(|flag| flag.value.clone());
+            config.healthcheck = Some(Healthcheck {
+                test,
+                interval: flag("interval").and_then(|value| duration(&value)),
+                timeout: flag("timeout").and_then(|value| duration(&value)),
+                start_period: flag("start-period").and_then(|value| duration(&value)),
+                retries: flag("retries").and_then(|value| value.parse().ok()),
+            });
+        },
+        _ => {},
+    }
+}
+
+// Parse a duration such as 1m30s into nanoseconds
+fn duration(value : &str) -> Option<u64> {
+    let units : [(&str, u64); 6] = [("ns", 1), ("us", 1_000), ("ms", 1_000_000), ("s", 1_000_000_000), ("m", 60_000_000_000), ("h", 3_600_000_000_000)];
+    let mut total = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest.find(|c : char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let number : f64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let (unit, scale) = units.iter().find(|(unit, _)| rest.starts_with(unit) && !rest[unit.len()..].starts_with('s'))?;
+        total += (number * *scale as f64) as u64;
+        rest = &rest[unit.len()..];
+    }
+    Some(total)
+}
+
+// Substitute $VAR, ${VAR}, ${VAR:-default} and ${VAR:+alternative}. Unknown variables expand to nothing.
+fn expand(value : &str, vars : &BTreeMap<String, String>) -> String {
+    let mut expanded = String::new();
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => expanded.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let inner : String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let (name, modifier) = match inner.find(':') {
+                    Some(index) => (&inner[..index], Some(&inner[index + 1..])),
+                    None => (&inner[..], None),
+                };
+                let current = vars.get(name).filter(|value| !value.is_empty());
+                match (modifier, current) {
+                    (Some(modifier), None) if modifier.starts_with('-') => expanded.push_str(&modifier[1..]),
+                    (Some(modifier), Some(_)) if modifier.starts_with('+') => expanded.push_str(&modifier[1..]),
+                    (Some(modifier), None) if modifier.starts_with('+') => {},
+                    (_, Some(current)) => expanded.push_str(current),
+                    (_, None) => {},
+                }
+            },
+            '$' if chars.peek().is_some_and(|c| c.is_alphanumeric() || *c == '_') => {
+                let mut name = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    name.push(*c);
+                    chars.next();
+                }
+                if let Some(current) = vars.get(&name) {
+                    expanded.push_str(current);
+                }
+            },
+            _ => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::oci::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn evaluates_override_semantics() {
+        let source = "FROM python:3.7-slim AS base\n\
+                      ARG VERSION=1.0\n\
+                      ENV APP_HOME=/app APP_VERSION=$VERSION\n\
+                      WORKDIR ${APP_HOME}\n\
+                      WORKDIR src\n\
+                      CMD python app.py\n\
+                      FROM base\n\
+                      ENV NAME World\n\
+                      EXPOSE 80 53/udp\n\
+                      ENTRYPOINT [\"python\"]\n\
+                      HEALTHCHECK --interval=1m30s --retries=3 CMD curl -f http://localhost/\n\
+                      USER app\n";
+
+        let config = preview(&parse(source).unwrap());
+        assert_eq!(config.env, vec!["APP_HOME=/app", "APP_VERSION=1.0", "NAME=World"]);
+        assert_eq!(config.working_dir, "/app/src");
+        assert_eq!(config.entrypoint, Some(vec![String::from("python")]));
+        assert_eq!(config.cmd, None);
+        assert_eq!(config.user, "app");
+        assert_eq!(config.exposed_ports.keys().collect::<Vec<_>>(), vec!["53/udp", "80/tcp"]);
+
+        let healthcheck = config.healthcheck.unwrap();
+        assert_eq!(healthcheck.test, vec!["CMD-SHELL", "curl -f http://localhost/"]);
+        assert_eq!(healthcheck.interval, Some(90_000_000_000));
+        assert_eq!(healthcheck.retries, Some(3));
+    }
+
+    #[test]
+    fn keeps_the_command_of_the_same_stage() {
+        let config = preview(&parse("FROM python:3.7-slim\nCMD [\"a\"]\nENTRYPOINT [\"b\"]\n").unwrap());
+        assert_eq!(config.entrypoint, Some(vec![String::from("b")]));
+        assert_eq!(config.cmd, Some(vec![String::from("a")]));
+
+        // Set in the base stage, so reset
+        let config = preview(&parse("FROM python:3.7-slim AS base\nCMD [\"a\"]\nFROM base\nENTRYPOINT [\"b\"]\n").unwrap());
+        assert_eq!(config.cmd, None);
+    }
+
+    #[test]
+    fn serializes_to_oci_schema() {
+        let config = preview(&parse("FROM scratch\nEXPOSE 80\nVOLUME /data\nCMD [\"app\"]\n").unwrap());
+        let json : serde_json::Value = serde_json::from_str(&config.to_json()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "ExposedPorts": { "80/tcp": {} },
+            "Cmd": ["app"],
+            "Volumes": { "/data": {} },
+        }));
+    }
+}
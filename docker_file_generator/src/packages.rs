@@ -0,0 +1,169 @@
+use crate::instruction::{self, Instruction};
+
+// The system package managers come with the base image, the language ones are chosen explicitly
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackageManager {
+    Apt,
+    Apk,
+    Dnf,
+    Microdnf,
+    Yum,
+    Pip,
+    Npm,
+    Cargo,
+}
+
+const APK_IMAGES : [&str; 2] = ["alpine", "wolfi"];
+const DNF_IMAGES : [&str; 7] = ["fedora", "centos", "rhel", "ubi", "rockylinux", "almalinux", "amazonlinux"];
+
+impl PackageManager {
+    // The system package manager of an image, from its name and tag: python:3.7-alpine uses apk, fedora:39 dnf.
+    // Official images are Debian based unless their tag says otherwise, and so is anything unknown.
+    // The minimal UBI images only have microdnf, and Amazon Linux 2 still uses yum.
+    pub fn detect(image : &str) -> PackageManager {
+        let (name, tag) = name_and_tag(image);
+        let (name, tag) = (name.as_str(), tag.as_str());
+        let family = |images : &[&str]| images.iter().any(|family| name.starts_with(family) || tag.contains(family));
+
+        if family(&APK_IMAGES) {
+            PackageManager::Apk
+        } else if name.starts_with("ubi") && name.contains("minimal") {
+            PackageManager::Microdnf
+        } else if name == "amazonlinux" && (tag == "2" || tag.starts_with("2.") || tag.starts_with("2-")) {
+            PackageManager::Yum
+        } else if family(&DNF_IMAGES) {
+            PackageManager::Dnf
+        } else {
+            PackageManager::Apt
+        }
+    }
+
+    // The system package manager of the stage the next instruction belongs to, following the stage aliases
+    pub fn for_stage(instructions : &[Instruction]) -> PackageManager {
+        instruction::base_image(instructions).map(PackageManager::detect).unwrap_or(PackageManager::Apt)
+    }
+
+    // Install the packages and clean the caches in the same layer, so they never reach the image.
+    // A package is pinned with name=version, written the way the manager expects it.
+    pub fn install<S : AsRef<str>>(&self, packages : &[S]) -> String {
+        let packages = self.packages(packages);
+        match self {
+            PackageManager::Apt => format!("apt-get update && apt-get install -y --no-install-recommends {} && rm -rf /var/lib/apt/lists/*", packages),
+            PackageManager::Apk => format!("apk add --no-cache {}", packages),
+            PackageManager::Dnf => format!("dnf install -y --setopt=install_weak_deps=False {} && dnf clean all", packages),
+            PackageManager::Microdnf => format!("microdnf install -y --setopt=install_weak_deps=0 {} && microdnf clean all", packages),
+            PackageManager::Yum => format!("yum install -y {} && yum clean all && rm -rf /var/cache/yum", packages),
+            PackageManager::Pip => format!("pip install --no-cache-dir {}", packages),
+            PackageManager::Npm => format!("npm install --global {} && npm cache clean --force", packages),
+            PackageManager::Cargo => format!("cargo install {} && rm -rf \"${{CARGO_HOME:-$HOME/.cargo}}/registry\"", packages),
+        }
+    }
+
+    // Install the build dependencies, run the command needing them, and remove them in the same layer.
+    // apk groups them in a virtual package, the other managers remove them by name.
+    pub fn with_build_deps<S : AsRef<str>>(&self, build : &[S], command : &str) -> String {
+        let names : Vec<&str> = build.iter().map(|package| name(package.as_ref())).collect();
+        let names = names.join(" ");
+        let packages = self.packages(build);
+        match self {
+            PackageManager::Apt => format!("apt-get update && apt-get install -y --no-install-recommends {} && {} \
+                                            && apt-get purge -y --auto-remove {} && rm -rf /var/lib/apt/lists/*", packages, command, names),
+            PackageManager::Apk => format!("apk add --no-cache --virtual .build-deps {} && {} && apk del .build-deps", packages, command),
+            PackageManager::Dnf => format!("dnf install -y --setopt=install_weak_deps=False {} && {} && dnf remove -y {} && dnf clean all",
+                                           packages, command, names),
+            PackageManager::Microdnf => format!("microdnf install -y --setopt=install_weak_deps=0 {} && {} && microdnf remove -y {} && microdnf clean all",
+                                                packages, command, names),
+            PackageManager::Yum => format!("yum install -y {} && {} && yum remove -y {} && yum clean all && rm -rf /var/cache/yum",
+                                           packages, command, names),
+            PackageManager::Pip => format!("pip install --no-cache-dir {} && {} && pip uninstall -y {}", packages, command, names),
+            PackageManager::Npm => format!("npm install --global {} && {} && npm uninstall --global {} && npm cache clean --force",
+                                           packages, command, names),
+            PackageManager::Cargo => format!("cargo install {} && {} && cargo uninstall {} && rm -rf \"${{CARGO_HOME:-$HOME/.cargo}}/registry\"",
+                                             packages, command, names),
+        }
+    }
+
+    // The program running the manager, as written in RUN steps
+    pub fn program(&self) -> &'static str {
+        match self {
+            PackageManager::Apt => "apt-get",
+            PackageManager::Apk => "apk",
+            PackageManager::Dnf => "dnf",
+            PackageManager::Microdnf => "microdnf",
+            PackageManager::Yum => "yum",
+            PackageManager::Pip => "pip",
+            PackageManager::Npm => "npm",
+            PackageManager::Cargo => "cargo",
+        }
+    }
+
+    fn packages<S : AsRef<str>>(&self, packages : &[S]) -> String {
+        packages.iter().map(|package| self.pin(package.as_ref())).collect::<Vec<String>>().join(" ")
+    }
+
+    fn pin(&self, package : &str) -> String {
+        let (name, version) = match package.split_once('=') {
+            Some((name, version)) => (name, version.trim_start_matches('=')),
+            None => return package.to_string(),
+        };
+        match self {
+            PackageManager::Apt | PackageManager::Apk => format!("{}={}", name, version),
+            PackageManager::Dnf | PackageManager::Microdnf | PackageManager::Yum => format!("{}-{}", name, version),
+            PackageManager::Pip => format!("{}=={}", name, version),
+            PackageManager::Npm | PackageManager::Cargo => format!("{}@{}", name, version),
+        }
+    }
+}
+
+fn name(package : &str) -> &str {
+    package.split('=').next().unwrap_or(package)
+}
+
+// The name of an image, without its registry nor namespace, and its tag, both in lower case
+pub(crate) fn name_and_tag(image : &str) -> (String, String) {
+    let image = image.to_lowercase();
+    let (name, tag) = match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (image.as_str(), ""),
+    };
+    (name.rsplit('/').next().unwrap_or(name).to_string(), tag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packages::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn detects_the_image_family() {
+        assert_eq!(PackageManager::detect("python:3.7-slim"), PackageManager::Apt);
+        assert_eq!(PackageManager::detect("python:3.7-alpine"), PackageManager::Apk);
+        assert_eq!(PackageManager::detect("docker.io/library/alpine:3.19"), PackageManager::Apk);
+        assert_eq!(PackageManager::detect("registry.access.redhat.com/ubi9/ubi"), PackageManager::Dnf);
+        assert_eq!(PackageManager::detect("registry.access.redhat.com/ubi9/ubi-minimal"), PackageManager::Microdnf);
+        assert_eq!(PackageManager::detect("registry.access.redhat.com/ubi8-minimal:8.9"), PackageManager::Microdnf);
+        assert_eq!(PackageManager::detect("amazonlinux:2"), PackageManager::Yum);
+        assert_eq!(PackageManager::detect("amazonlinux:2023"), PackageManager::Dnf);
+        assert_eq!(PackageManager::detect("localhost:5000/fedora"), PackageManager::Dnf);
+
+        let instructions = parse("FROM alpine:3.19 AS base\nFROM debian:bookworm AS other\nFROM base\n").unwrap();
+        assert_eq!(PackageManager::for_stage(&instructions), PackageManager::Apk);
+    }
+
+    #[test]
+    fn pins_and_cleans_up() {
+        assert_eq!(PackageManager::Apt.install(&["curl=7.88.1-10", "ca-certificates"]),
+                   "apt-get update && apt-get install -y --no-install-recommends curl=7.88.1-10 ca-certificates && rm -rf /var/lib/apt/lists/*");
+        assert_eq!(PackageManager::Pip.install(&["requests==2.31.0"]), "pip install --no-cache-dir requests==2.31.0");
+        assert_eq!(PackageManager::Npm.install(&["typescript=5.4"]), "npm install --global typescript@5.4 && npm cache clean --force");
+        assert_eq!(PackageManager::Dnf.install(&["git=2.43"]), "dnf install -y --setopt=install_weak_deps=False git-2.43 && dnf clean all");
+        assert_eq!(PackageManager::Microdnf.install(&["git"]), "microdnf install -y --setopt=install_weak_deps=0 git && microdnf clean all");
+        assert_eq!(PackageManager::Yum.install(&["git=2.40"]), "yum install -y git-2.40 && yum clean all && rm -rf /var/cache/yum");
+
+        assert_eq!(PackageManager::Apk.with_build_deps(&["gcc", "musl-dev"], "pip install -r requirements.txt"),
+                   "apk add --no-cache --virtual .build-deps gcc musl-dev && pip install -r requirements.txt && apk del .build-deps");
+        assert_eq!(PackageManager::Apt.with_build_deps(&["gcc=4:12.2.0-3"], "make"),
+                   "apt-get update && apt-get install -y --no-install-recommends gcc=4:12.2.0-3 && make \
+                    && apt-get purge -y --auto-remove gcc && rm -rf /var/lib/apt/lists/*");
+    }
+}
@@ -0,0 +1,527 @@
+use failure::Fail;
+
+use crate::instruction::{escape, Command, Flag, Heredoc, Instruction};
+
+#[derive(Fail, Debug, PartialEq)]
+#[fail(display = "Line {}: {}", line, reason)]
+pub struct ParseError {
+    pub line   : usize,
+    pub reason : String,
+}
+
+// Parse a whole Dockerfile. Comments found inside a multi-line instruction are kept,
+// and placed right before the instruction they were written in.
+pub fn parse(source : &str) -> Result<Vec<Instruction>, ParseError> {
+    Ok(parse_with_lines(source)?.into_iter().map(|(_, instruction)| instruction).collect())
+}
+
+// Same as parse, along with the line each instruction starts at
+pub fn parse_with_lines(source : &str) -> Result<Vec<(usize, Instruction)>, ParseError> {
+    let mut instructions = Vec::new();
+    let mut lines = source.lines().enumerate();
+
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() {
+            instructions.push((number + 1, Instruction::Empty));
+            continue;
+        }
+        if line.starts_with('#') {
+            instructions.push((number + 1, comment(line)));
+            continue;
+        }
+
+        let mut logical = String::new();
+        let mut current = line;
+        loop {
+            let part = match continuation(current) {
+                Some(part) => part,
+                None => {
+                    logical.push_str(current);
+                    break;
+                },
+            };
+            logical.push_str(part);
+
+            let next = lines.by_ref()
+                .map(|(index, next)| (index, next.trim()))
+                .filter(|(_, next)| !next.is_empty())
+                .find(|(index, next)| {
+                    if next.starts_with('#') {
+                        instructions.push((index + 1, comment(next)));
+                        false
+                    } else {
+                        true
+                    }
+                });
+
+            match next {
+                Some((_, next)) => {
+                    if !logical.ends_with(' ') {
+                        logical.push(' ');
+                    }
+                    current = next;
+                },
+                None => break,
+            }
+        }
+
+        let mut instruction = parse_line(&logical).map_err(|reason| ParseError { line: number + 1, reason })?;
+
+        // The bodies of heredocs follow the instruction, in the order of their markers, verbatim
+        let mut bodies = Vec::new();
+        for (delimiter, strip_tabs) in markers(&instruction) {
+            let mut body = String::new();
+            let terminated = lines.by_ref().any(|(_, line)| {
+                let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+                if line == delimiter {
+                    return true;
+                }
+                body.push_str(line);
+                body.push('\n');
+                false
+            });
+            if !terminated {
+                return Err(ParseError { line: number + 1, reason: format!("Heredoc {} is never terminated", delimiter) });
+            }
+            bodies.push(Heredoc { delimiter, body });
+        }
+        if let Instruction::Run { heredocs, .. } | Instruction::Add { heredocs, .. } | Instruction::Copy { heredocs, .. } = &mut instruction {
+            *heredocs = bodies;
+        }
+        instructions.push((number + 1, instruction));
+    }
+
+    Ok(instructions)
+}
+
+// Parse a single logical line, with its continuations already joined
+pub fn parse_line(line : &str) -> Result<Instruction, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(Instruction::Empty);
+    }
+    if line.starts_with('#') {
+        return Ok(comment(line));
+    }
+
+    let (keyword, rest) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+    let keyword = keyword.to_uppercase();
+    if rest.is_empty() && keyword != "HEALTHCHECK" {
+        return Err(format!("{} requires at least one argument", keyword));
+    }
+
+    let instruction = match keyword.as_str() {
+        "FROM" => {
+            let (flags, rest) = flags(rest);
+            let words : Vec<&str> = rest.split_whitespace().collect();
+            match words.as_slice() {
+                [image] => Instruction::From { flags, image: image.to_string(), alias: None },
+                [image, as_keyword, alias] if as_keyword.eq_ignore_ascii_case("AS") =>
+                    Instruction::From { flags, image: image.to_string(), alias: Some(alias.to_string()) },
+                _ => return Err(String::from("FROM expects an image, optionally followed by AS <name>")),
+            }
+        },
+        "RUN" => {
+            let (flags, rest) = flags(rest);
+            if rest.is_empty() {
+                return Err(String::from("RUN requires a command"));
+            }
+            Instruction::Run { flags, command: command(rest), heredocs: Vec::new() }
+        },
+        "CMD" => Instruction::Cmd(command(rest)),
+        "ENTRYPOINT" => Instruction::Entrypoint(command(rest)),
+        "LABEL" => Instruction::Label(pairs(&keyword, rest)?),
+        "MAINTAINER" => Instruction::Maintainer(rest.to_string()),
+        "EXPOSE" => Instruction::Expose(rest.split_whitespace().map(String::from).collect()),
+        "ENV" => Instruction::Env(env(rest)?),
+        "ADD" | "COPY" => {
+            let (flags, rest) = flags(rest);
+            let mut sources = json_array(rest).unwrap_or_else(|| rest.split_whitespace().map(String::from).collect());
+            if sources.len() < 2 {
+                return Err(format!("{} requires at least one source and a destination", keyword));
+            }
+            let destination = sources.pop().unwrap();
+            if keyword == "ADD" {
+                Instruction::Add { flags, sources, destination, heredocs: Vec::new() }
+            } else {
+                Instruction::Copy { flags, sources, destination, heredocs: Vec::new() }
+            }
+        },
+        "VOLUME" => Instruction::Volume(json_array(rest).unwrap_or_else(|| rest.split_whitespace().map(String::from).collect())),
+        "USER" => Instruction::User(rest.to_string()),
+        "WORKDIR" => Instruction::WorkDir(rest.to_string()),
+        "ARG" => {
+            let words = lex(rest, true);
+            if words.len() != 1 {
+                return Err(String::from("ARG expects a single name, optionally with a default value"));
+            }
+            match words[0].find('=') {
+                Some(index) => Instruction::Arg { name: words[0][..index].to_string(), default: Some(words[0][index + 1..].to_string()) },
+                None => Instruction::Arg { name: words[0].clone(), default: None },
+            }
+        },
+        "ONBUILD" => {
+            let trigger = rest.split_whitespace().next().unwrap_or("").to_uppercase();
+            if ["ONBUILD", "FROM", "MAINTAINER"].contains(&trigger.as_str()) {
+                return Err(format!("{} is not allowed as an ONBUILD trigger", trigger));
+            }
+            Instruction::OnBuild(Box::new(parse_line(rest)?))
+        },
+        "STOPSIGNAL" => Instruction::StopSignal(rest.to_string()),
+        "HEALTHCHECK" => {
+            let (flags, rest) = flags(rest);
+            let (check, command_line) = match rest.find(char::is_whitespace) {
+                Some(index) => (&rest[..index], rest[index..].trim()),
+                None => (rest, ""),
+            };
+            match check.to_uppercase().as_str() {
+                "NONE" if command_line.is_empty() => Instruction::Healthcheck { flags, command: None },
+                "CMD" if !command_line.is_empty() => Instruction::Healthcheck { flags, command: Some(command(command_line)) },
+                _ => return Err(String::from("HEALTHCHECK expects either NONE or CMD <command>")),
+            }
+        },
+        "SHELL" => match json_array(rest) {
+            Some(args) => Instruction::Shell(args),
+            None => return Err(String::from("SHELL requires the arguments in JSON form")),
+        },
+        _ => return Err(format!("Unknown instruction {}", keyword)),
+    };
+
+    Ok(instruction)
+}
+
+fn comment(line : &str) -> Instruction {
+    Instruction::Comment(line[1..].trim().to_string())
+}
+
+// A line ending with a backslash continues on the next line
+fn continuation(line : &str) -> Option<&str> {
+    line.trim_end().strip_suffix('\\').map(str::trim_end)
+}
+
+// The delimiters of the `<<EOF` markers of RUN, COPY and ADD, and whether they strip leading tabs (`<<-EOF`)
+fn markers(instruction : &Instruction) -> Vec<(String, bool)> {
+    let text = match instruction {
+        Instruction::Run { command: Command::Shell(line), .. } => line.clone(),
+        Instruction::Add { sources, .. } | Instruction::Copy { sources, .. } => sources.join(" "),
+        _ => return Vec::new(),
+    };
+
+    // Only an unquoted `<<` starting a word is a marker, optionally after the number of a file descriptor.
+    // Here-strings (<<<) are left to the shell.
+    let mut markers = Vec::new();
+    let mut quote = None;
+    let mut word_start = true;
+    let mut skip_to = 0;
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        if index < skip_to {
+            continue;
+        }
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"'), '\\') => { chars.next(); },
+            (Some(_), _) => {},
+            (None, '\\') => { chars.next(); word_start = false },
+            (None, '"' | '\'') => { quote = Some(c); word_start = false },
+            (None, c) if c.is_whitespace() => word_start = true,
+            (None, c) if c.is_ascii_digit() => {},
+            (None, '<') if word_start && text[index..].starts_with("<<") => {
+                let rest = &text[index..];
+                if rest.starts_with("<<<") {
+                    skip_to = index + rest.len() - rest.trim_start_matches('<').len();
+                } else {
+                    let (delimiter, length) = marker(&rest[2..]);
+                    markers.extend(delimiter);
+                    skip_to = index + 2 + length;
+                }
+                word_start = false;
+            },
+            _ => word_start = false,
+        }
+    }
+    markers
+}
+
+// The delimiter at the start of the text following `<<`, and the length it takes
+fn marker(text : &str) -> (Option<(String, bool)>, usize) {
+    let mut rest = text;
+    let strip_tabs = rest.starts_with('-');
+    if strip_tabs {
+        rest = &rest[1..];
+    }
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'');
+    if let Some(quote) = quote {
+        rest = &rest[quote.len_utf8()..];
+    }
+    let end = rest.find(|c : char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+    if end == 0 {
+        return (None, 0);
+    }
+    let delimiter = rest[..end].to_string();
+    rest = &rest[end..];
+    if let Some(quote) = quote {
+        rest = rest.strip_prefix(quote).unwrap_or(rest);
+    }
+    (Some((delimiter, strip_tabs)), text.len() - rest.len())
+}
+
+fn flags(rest : &str) -> (Vec<Flag>, &str) {
+    let mut flags = Vec::new();
+    let mut rest = rest;
+    while rest.starts_with("--") {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let flag = &rest[2..end];
+        flags.push(match flag.find('=') {
+            Some(index) => Flag { name: flag[..index].to_string(), value: Some(flag[index + 1..].to_string()) },
+            None => Flag { name: flag.to_string(), value: None },
+        });
+        rest = rest[end..].trim_start();
+    }
+    (flags, rest)
+}
+
+fn command(rest : &str) -> Command {
+    match json_array(rest) {
+        Some(args) if rest.starts_with('[') => Command::Exec(args),
+        _ => Command::Shell(rest.to_string()),
+    }
+}
+
+fn env(rest : &str) -> Result<Vec<(String, String)>, String> {
+    let first = rest.split_whitespace().next().unwrap_or("");
+    if first.contains('=') {
+        return pairs("ENV", rest);
+    }
+
+    // The legacy form: ENV <key> <value>, where the value is the rest of the line
+    let value = rest[first.len()..].trim();
+    if value.is_empty() {
+        return Err(String::from("ENV requires a value for the variable"));
+    }
+    Ok(vec![(first.to_string(), lex(value, false).concat())])
+}
+
+fn pairs(keyword : &str, rest : &str) -> Result<Vec<(String, String)>, String> {
+    lex(rest, true).into_iter()
+        .map(|word| match word.find('=') {
+            Some(index) if index > 0 => Ok((word[..index].to_string(), word[index + 1..].to_string())),
+            _ => Err(format!("{} expects <key>=<value> pairs, found {}", keyword, word)),
+        })
+        .collect()
+}
+
+// Process quotes and escapes the way the Dockerfile frontend does for ENV, LABEL and ARG.
+// When split is true the input is broken into whitespace separated words.
+fn lex(input : &str, split : bool) -> Vec<String> {
+    let mut words = Vec::new();
+    // The characters of the word, with whether each one was escaped, so an escaped `$` is kept escaped
+    let mut word = Vec::new();
+    let mut in_word = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() && split => {
+                if in_word {
+                    words.push(escape(&word));
+                    word.clear();
+                    in_word = false;
+                }
+            },
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(escaped) => word.push((escaped, true)),
+                    None => word.push(('\\', false)),
+                }
+            },
+            '"' => {
+                in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"' | '\\' | '$')) => word.push((chars.next().unwrap(), true)),
+                        _ => word.push((c, false)),
+                    }
+                }
+            },
+            '\'' => {
+                in_word = true;
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push((c, true));
+                }
+            },
+            _ => {
+                in_word = true;
+                word.push((c, false));
+            },
+        }
+    }
+
+    if in_word {
+        words.push(escape(&word));
+    }
+    words
+}
+
+// Parse a JSON array of strings, as used by the exec form. Returns None when the input is anything else.
+pub(crate) fn json_array(input : &str) -> Option<Vec<String>> {
+    let mut chars = input.trim().chars().peekable();
+    let mut values = Vec::new();
+
+    let skip_whitespace = |chars : &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    };
+
+    if chars.next()? != '[' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&']') {
+        chars.next();
+        return if chars.next().is_none() { Some(values) } else { None };
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next()? != '"' {
+            return None;
+        }
+
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    'b' => value.push('\u{8}'),
+                    'f' => value.push('\u{c}'),
+                    'u' => {
+                        let code : String = chars.by_ref().take(4).collect();
+                        value.push(std::char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    },
+                    c @ '"' | c @ '\\' | c @ '/' => value.push(c),
+                    _ => return None,
+                },
+                c => value.push(c),
+            }
+        }
+        values.push(value);
+
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            ']' => break,
+            _ => return None,
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next().is_none() { Some(values) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+
+    #[test]
+    fn parses_continuations_and_hoists_comments() {
+        let source = "FROM python:3.7-slim AS base\n\
+                      RUN apt-get update \\\n\
+                      # install curl\n\
+                          && apt-get install -y curl\n";
+
+        assert_eq!(parse(source).unwrap(), vec![
+            Instruction::From { flags: vec![], image: String::from("python:3.7-slim"), alias: Some(String::from("base")) },
+            Instruction::Comment(String::from("install curl")),
+            Instruction::Run { flags: vec![], command: Command::Shell(String::from("apt-get update && apt-get install -y curl")), heredocs: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn parses_env_forms() {
+        assert_eq!(parse_line("ENV NAME World").unwrap(), Instruction::Env(vec![(String::from("NAME"), String::from("World"))]));
+        assert_eq!(parse_line(r#"env A="hello \"world\"" B=2"#).unwrap(), Instruction::Env(vec![
+            (String::from("A"), String::from("hello \"world\"")),
+            (String::from("B"), String::from("2")),
+        ]));
+    }
+
+    #[test]
+    fn keeps_escaped_dollars_escaped() {
+        let env = |value : &str| Instruction::Env(vec![(String::from("A"), String::from(value))]);
+        assert_eq!(parse_line(r"ENV A=\$HOME").unwrap(), env(r"\$HOME"));
+        assert_eq!(parse_line(r#"ENV A="\$HOME""#).unwrap(), env(r"\$HOME"));
+        assert_eq!(parse_line("ENV A='$HOME'").unwrap(), env(r"\$HOME"));
+        assert_eq!(parse_line(r"ENV A=\\$HOME").unwrap(), env(r"\\$HOME"));
+        assert_eq!(parse_line(r#"ENV A="C:\\" B=\a"#).unwrap(), Instruction::Env(vec![
+            (String::from("A"), String::from(r"C:\")),
+            (String::from("B"), String::from("a")),
+        ]));
+    }
+
+    #[test]
+    fn parses_exec_form_and_flags() {
+        assert_eq!(parse_line(r#"COPY --from=build --chown=app ["a b", "/app/"]"#).unwrap(), Instruction::Copy {
+            flags: vec![Flag::new("from", "build"), Flag::new("chown", "app")],
+            sources: vec![String::from("a b")],
+            destination: String::from("/app/"),
+            heredocs: vec![],
+        });
+        assert_eq!(parse_line(r#"CMD ["python", "app.py"]"#).unwrap(), Instruction::Cmd(Command::Exec(vec![String::from("python"), String::from("app.py")])));
+        assert_eq!(parse_line(r#"CMD ["python", app.py]"#).unwrap(), Instruction::Cmd(Command::Shell(String::from(r#"["python", app.py]"#))));
+    }
+
+    #[test]
+    fn parses_heredocs_verbatim() {
+        let source = "RUN <<EOF\n  set -e\n  echo \"$HOME\" \\\nEOF\n\
+                      COPY <<-'A' <<B /etc/\n\tfirst\n\tA\nsecond\nB\n\
+                      RUN cat <<<word\n";
+
+        let instructions = parse_with_lines(source).unwrap();
+        assert_eq!(instructions[0].1.heredocs(), &[Heredoc { delimiter: String::from("EOF"), body: String::from("  set -e\n  echo \"$HOME\" \\\n") }]);
+        assert_eq!(instructions[1].0, 5);
+        assert_eq!(instructions[1].1.heredocs(), &[
+            Heredoc { delimiter: String::from("A"), body: String::from("first\n") },
+            Heredoc { delimiter: String::from("B"), body: String::from("second\n") },
+        ]);
+        assert_eq!(instructions[2].0, 10);
+        assert!(instructions[2].1.heredocs().is_empty());
+
+        assert_eq!(parse("RUN <<EOF\necho\n"), Err(ParseError { line: 1, reason: String::from("Heredoc EOF is never terminated") }));
+    }
+
+    #[test]
+    fn ignores_shifts_and_quoted_markers() {
+        for line in ["RUN echo \"a<<b\"", "RUN echo $((1<<3))", "RUN echo 'x <<EOF'", "RUN echo \\<<EOF", "RUN echo $(( 1 << 3 ))"] {
+            let instructions = parse(&format!("{}\n", line)).unwrap();
+            assert_eq!(instructions.len(), 1, "{}", line);
+            assert!(instructions[0].heredocs().is_empty(), "{}", line);
+        }
+
+        let instructions = parse("RUN cat 3<<EOF \"<<A\" && cat <<-\"B\"\nfirst\nEOF\n\tsecond\nB\n").unwrap();
+        assert_eq!(instructions[0].heredocs(), &[
+            Heredoc { delimiter: String::from("EOF"), body: String::from("first\n") },
+            Heredoc { delimiter: String::from("B"), body: String::from("second\n") },
+        ]);
+    }
+
+    #[test]
+    fn reports_line_of_invalid_instruction() {
+        assert_eq!(parse("FROM scratch\n\nCOPY onlyone\n").unwrap_err().line, 3);
+        assert!(parse("FORM scratch").is_err());
+        assert!(parse(&format!("{}RUN make", "ONBUILD ".repeat(100000))).is_err());
+    }
+}
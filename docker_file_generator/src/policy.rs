@@ -0,0 +1,370 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::generator::GenerateError;
+use crate::instruction::{self, Flag, Instruction};
+use crate::parser::{self, ParseError};
+use crate::visit::Transform;
+
+const DEFAULT_REGISTRY : &str = "docker.io";
+
+// An image reference split into its parts, with Docker Hub's implicit registry and library namespace filled in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageRef {
+    pub registry   : String,
+    pub repository : String,
+    pub tag        : Option<String>,
+    pub digest     : Option<String>,
+}
+
+impl ImageRef {
+    pub fn parse(image : &str) -> ImageRef {
+        let (rest, digest) = match image.find('@') {
+            Some(index) => (&image[..index], Some(image[index + 1..].to_string())),
+            None => (image, None),
+        };
+        let (name, tag) = match rest.rfind(':') {
+            Some(index) if !rest[index..].contains('/') => (&rest[..index], Some(rest[index + 1..].to_string())),
+            _ => (rest, None),
+        };
+
+        let (registry, repository) = match name.find('/') {
+            Some(index) if name[..index].contains(['.', ':']) || &name[..index] == "localhost" =>
+                (name[..index].to_string(), name[index + 1..].to_string()),
+            Some(_) => (DEFAULT_REGISTRY.to_string(), name.to_string()),
+            None => (DEFAULT_REGISTRY.to_string(), format!("library/{}", name)),
+        };
+        ImageRef { registry, repository, tag, digest }
+    }
+
+    // The fully qualified name, without tag nor digest
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+
+    // Does the reference designate this image. A pattern without tag or digest matches all of them.
+    pub(crate) fn matches(&self, pattern : &ImageRef) -> bool {
+        self.name() == pattern.name()
+            && (pattern.tag.is_none() || pattern.tag == self.tag)
+            && (pattern.digest.is_none() || pattern.digest == self.digest)
+    }
+
+    fn reference(&self) -> String {
+        let mut reference = String::new();
+        if let Some(ref tag) = self.tag {
+            reference.push(':');
+            reference.push_str(tag);
+        }
+        if let Some(ref digest) = self.digest {
+            reference.push('@');
+            reference.push_str(digest);
+        }
+        reference
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    mirrors : toml::Table,
+    #[serde(default)]
+    replace : toml::Table,
+    #[serde(default)]
+    allow   : Vec<String>,
+}
+
+// Rules applied to every image a Dockerfile pulls: first the replacements, then the allowlist, then the mirrors.
+//
+// ```toml
+// allow = ["python", "debian", "gcr.io/distroless/*"]
+//
+// [replace]
+// "python:2.7-slim" = "python:3.7-slim"
+//
+// [mirrors]
+// "docker.io" = "mirror.internal/dockerhub"
+// ```
+#[derive(Clone, Debug, Default)]
+pub struct ImagePolicy {
+    // Registry prefixes, such as docker.io or gcr.io/distroless, and the mirror replacing them
+    pub mirrors      : Vec<(String, String)>,
+    // Images, with or without a tag, and the approved image to use instead
+    pub replacements : Vec<(String, String)>,
+    // When not empty, the only images allowed. A trailing * matches any fully qualified name starting with the prefix.
+    pub allow        : Vec<String>,
+    audit            : Audit,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allowed,
+    Rewritten,
+    Denied,
+    // Stage references, scratch and images built from variables can not be checked
+    Skipped,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    // Index of the instruction, and its line in the Dockerfile
+    pub instruction : usize,
+    pub line        : usize,
+    pub keyword     : &'static str,
+    pub original    : String,
+    pub image       : String,
+    pub decision    : Decision,
+    pub reasons     : Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Audit {
+    pub entries : Vec<AuditEntry>,
+}
+
+impl ImagePolicy {
+    pub fn load(path : &Path) -> Result<ImagePolicy, GenerateError> {
+        let content = fs::read_to_string(path).map_err(GenerateError::IO)?;
+        ImagePolicy::from_toml(&content).map_err(|reason| GenerateError::InvalidArgument(format!("{}: {}", path.display(), reason)))
+    }
+
+    pub fn from_toml(content : &str) -> Result<ImagePolicy, String> {
+        let file : PolicyFile = toml::from_str(content).map_err(|error| error.to_string())?;
+        let strings = |table : toml::Table, section : &str| -> Result<Vec<(String, String)>, String> {
+            table.into_iter()
+                .map(|(key, value)| match value {
+                    toml::Value::String(value) => Ok((key, value)),
+                    _ => Err(format!("[{}] {} must be a string", section, key)),
+                })
+                .collect()
+        };
+
+        Ok(ImagePolicy {
+            mirrors: strings(file.mirrors, "mirrors")?,
+            replacements: strings(file.replace, "replace")?,
+            allow: file.allow,
+            audit: Audit::default(),
+        })
+    }
+
+    // The audit of the last instructions the policy was applied to as a transform
+    pub fn audit(&self) -> &Audit {
+        &self.audit
+    }
+
+    // Rewrite the image references of the instructions. Denied images are left as they are, and reported in the audit.
+    pub fn apply(&self, instructions : &[Instruction]) -> (Vec<Instruction>, Audit) {
+        let mut audit = Audit::default();
+        let mut stages : BTreeSet<String> = BTreeSet::new();
+        let mut count = 0;
+        let mut rewritten = Vec::new();
+        let lines = instruction::rendered_lines(instructions);
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            let mut instruction = instruction.clone();
+            match &mut instruction {
+                Instruction::From { image, alias, .. } => {
+                    audit.entries.push(self.check(index, lines[index], "FROM", image, &stages));
+                    stages.insert(count.to_string());
+                    count += 1;
+                    if let Some(alias) = alias {
+                        stages.insert(alias.clone());
+                    }
+                },
+                Instruction::Copy { flags, .. } => {
+                    if let Some(Flag { value: Some(image), .. }) = flags.iter_mut().find(|flag| flag.name == "from") {
+                        audit.entries.push(self.check(index, lines[index], "COPY", image, &stages));
+                    }
+                },
+                _ => {},
+            }
+            rewritten.push(instruction);
+        }
+        (rewritten, audit)
+    }
+
+    // Apply the policy to a Dockerfile's source, reporting the lines as written in it
+    pub fn apply_source(&self, source : &str) -> Result<(Vec<Instruction>, Audit), ParseError> {
+        let parsed = parser::parse_with_lines(source)?;
+        let instructions : Vec<Instruction> = parsed.iter().map(|(_, instruction)| instruction.clone()).collect();
+
+        let (rewritten, mut audit) = self.apply(&instructions);
+        for entry in &mut audit.entries {
+            entry.line = parsed[entry.instruction].0;
+        }
+        Ok((rewritten, audit))
+    }
+
+    // Decide for one image reference, and rewrite it in place
+    fn check(&self, index : usize, line : usize, keyword : &'static str, image : &mut String, stages : &BTreeSet<String>) -> AuditEntry {
+        let original = image.clone();
+        let mut entry = AuditEntry { instruction: index, line, keyword, original: original.clone(), image: original.clone(), decision: Decision::Allowed, reasons: Vec::new() };
+
+        if stages.contains(&original) || original == "scratch" || original.contains('$') {
+            entry.decision = Decision::Skipped;
+            return entry;
+        }
+
+        let mut reference = ImageRef::parse(&original);
+        let mut written = original.clone();
+        // A rule naming the tag wins over one for the whole image
+        let replacement = self.replacements.iter()
+            .map(|(from, to)| (from, to, ImageRef::parse(from)))
+            .filter(|(_, _, pattern)| reference.matches(pattern))
+            .max_by_key(|(_, _, pattern)| pattern.tag.is_some() as u8 + pattern.digest.is_some() as u8);
+        if let Some((from, to, _)) = replacement {
+            let replacement = ImageRef::parse(to);
+            written = if replacement.tag.is_none() && replacement.digest.is_none() {
+                format!("{}{}", to, reference.reference())
+            } else {
+                to.clone()
+            };
+            reference = ImageRef::parse(&written);
+            entry.reasons.push(format!("replaced by {} per rule {}", to, from));
+        }
+
+        if !self.allowed(&reference) {
+            entry.decision = Decision::Denied;
+            entry.reasons.push(format!("{} is not on the allowlist", reference.name()));
+            return entry;
+        }
+
+        // The longest registry prefix wins, and only matches whole path components
+        let name = reference.name();
+        let mirror = self.mirrors.iter()
+            .filter(|(prefix, _)| name == **prefix || name.starts_with(&format!("{}/", prefix.trim_end_matches('/'))))
+            .max_by_key(|(prefix, _)| prefix.len());
+        if let Some((prefix, mirror)) = mirror {
+            written = format!("{}{}{}", mirror.trim_end_matches('/'), &name[prefix.trim_end_matches('/').len()..], reference.reference());
+            entry.reasons.push(format!("mirrored from {} to {}", prefix, mirror));
+        }
+
+        if written != original {
+            entry.decision = Decision::Rewritten;
+            entry.image = written.clone();
+            *image = written;
+        }
+        entry
+    }
+
+    fn allowed(&self, reference : &ImageRef) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => reference.name().starts_with(prefix),
+            None => reference.matches(&ImageRef::parse(pattern)),
+        })
+    }
+}
+
+// As a pass of a pipeline, denied images are kept as they are; check audit() afterwards
+impl Transform for ImagePolicy {
+    fn transform(&mut self, instructions : Vec<Instruction>) -> Vec<Instruction> {
+        let (rewritten, audit) = self.apply(&instructions);
+        self.audit = audit;
+        rewritten
+    }
+}
+
+impl Audit {
+    pub fn denied(&self) -> impl Iterator<Item = &AuditEntry> {
+        self.entries.iter().filter(|entry| entry.decision == Decision::Denied)
+    }
+
+    pub fn is_denied(&self) -> bool {
+        self.denied().next().is_some()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("an audit is always serializable")
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let decision = match self.decision {
+            Decision::Allowed => "allowed",
+            Decision::Rewritten => "rewritten",
+            Decision::Denied => "denied",
+            Decision::Skipped => "skipped",
+        };
+        write!(f, "line {}: {} {} {}", self.line, self.keyword, self.original, decision)?;
+        if self.decision == Decision::Rewritten {
+            write!(f, " to {}", self.image)?;
+        }
+        if !self.reasons.is_empty() {
+            write!(f, " ({})", self.reasons.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Audit {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::policy::*;
+    use crate::parser::parse;
+
+    const POLICY : &str = r#"
+        allow = ["python", "debian:bookworm-slim", "gcr.io/distroless/*"]
+
+        [replace]
+        "python:2.7-slim" = "python:3.7-slim"
+
+        [mirrors]
+        "docker.io" = "mirror.internal/dockerhub"
+        "gcr.io/distroless" = "mirror.internal/distroless"
+    "#;
+
+    #[test]
+    fn parses_image_references() {
+        assert_eq!(ImageRef::parse("python:3.7-slim").name(), "docker.io/library/python");
+        assert_eq!(ImageRef::parse("localhost:5000/app").registry, "localhost:5000");
+        let reference = ImageRef::parse("gcr.io/distroless/cc@sha256:abc");
+        assert_eq!((reference.repository.as_str(), reference.tag, reference.digest.as_deref()), ("distroless/cc", None, Some("sha256:abc")));
+    }
+
+    #[test]
+    fn rewrites_and_audits_references() {
+        let policy = ImagePolicy::from_toml(POLICY).unwrap();
+        let instructions = parse("FROM python:2.7-slim AS build\n\
+                                  FROM gcr.io/distroless/python3\n\
+                                  COPY --from=build /app /app\n\
+                                  COPY --from=debian:bookworm-slim /etc/ssl /etc/ssl\n\
+                                  COPY --from=node:20 /usr/local/bin/node /usr/local/bin/\n").unwrap();
+
+        let (rewritten, audit) = policy.apply(&instructions);
+        let rendered : Vec<String> = rewritten.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(rendered[0], "FROM mirror.internal/dockerhub/library/python:3.7-slim AS build");
+        assert_eq!(rendered[1], "FROM mirror.internal/distroless/python3");
+        assert_eq!(rendered[3], "COPY --from=mirror.internal/dockerhub/library/debian:bookworm-slim /etc/ssl /etc/ssl");
+        assert_eq!(rendered[4], instructions[4].to_string());
+
+        let decisions : Vec<Decision> = audit.entries.iter().map(|entry| entry.decision).collect();
+        assert_eq!(decisions, vec![Decision::Rewritten, Decision::Rewritten, Decision::Skipped, Decision::Rewritten, Decision::Denied]);
+        assert_eq!(audit.denied().next().unwrap().to_string(), "line 5: COPY node:20 denied (docker.io/library/node is not on the allowlist)");
+        assert!(ImagePolicy::from_toml("[mirrors]\n\"docker.io\" = 1\n").is_err());
+    }
+
+    #[test]
+    fn reports_the_lines_of_the_source() {
+        let policy = ImagePolicy::from_toml(POLICY).unwrap();
+        let (_, audit) = policy.apply_source("# build\nFROM python:2.7-slim \\\n  AS build\n\nFROM node:20\n").unwrap();
+        assert_eq!(audit.entries.iter().map(|entry| (entry.instruction, entry.line)).collect::<Vec<_>>(), vec![(1, 2), (3, 5)]);
+        assert_eq!(audit.denied().next().unwrap().to_string(), "line 5: FROM node:20 denied (docker.io/library/node is not on the allowlist)");
+
+        let (_, audit) = policy.apply(&parse("FROM python:2.7-slim AS build\nRUN <<EOF\nmake\nEOF\nFROM node:20\n").unwrap());
+        assert_eq!(audit.entries.iter().map(|entry| (entry.instruction, entry.line)).collect::<Vec<_>>(), vec![(0, 1), (2, 5)]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::instruction::Instruction;
+use crate::parser::{self, ParseError};
+
+const HEADER : &str = "Generated by dock_gen";
+const HASH_KEY : &str = "spec-hash";
+const LABEL_PREFIX : &str = "io.dock-gen.";
+
+// What the Dockerfile was generated from, recorded in a header comment and in labels of the final stage
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provenance {
+    pub version   : String,
+    // Parameters the generation depends on, such as the Python version
+    pub params    : Vec<(String, String)>,
+    pub commit    : Option<String>,
+    // Off by default, for reproducible output. SOURCE_DATE_EPOCH is used instead of the clock when it is set.
+    pub timestamp : bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verification {
+    Verified,
+    // The instructions were edited since the Dockerfile was generated
+    Modified { recorded : String, actual : String },
+    // The Dockerfile has no recorded hash
+    Unrecorded,
+}
+
+impl Default for Provenance {
+    fn default() -> Provenance {
+        Provenance {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            params: Vec::new(),
+            commit: None,
+            timestamp: false,
+        }
+    }
+}
+
+impl Provenance {
+    pub fn param<V : ToString>(mut self, key : &str, value : V) -> Provenance {
+        self.params.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Record the commit of the git repository containing the directory, if there is one
+    pub fn commit_of(mut self, dir : &Path) -> Provenance {
+        self.commit = git_commit(dir);
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp : bool) -> Provenance {
+        self.timestamp = timestamp;
+        self
+    }
+
+    // Add the header after the parser directives, and the labels at the end of the final stage
+    pub fn apply(&self, instructions : Vec<Instruction>) -> Vec<Instruction> {
+        let hash = spec_hash(&instructions);
+        let created = if self.timestamp { Some(now()) } else { None };
+
+        let mut header = vec![format!("{} {}", HEADER, self.version), format!("{}: {}", HASH_KEY, hash)];
+        header.extend(self.params.iter().map(|(key, value)| format!("param.{}: {}", key, value)));
+        header.extend(self.commit.iter().map(|commit| format!("git-commit: {}", commit)));
+        header.extend(created.iter().map(|created| format!("created: {}", created)));
+
+        let mut labels = vec![(format!("{}version", LABEL_PREFIX), self.version.clone()), (format!("{}{}", LABEL_PREFIX, HASH_KEY), hash)];
+        labels.extend(self.params.iter().map(|(key, value)| (format!("{}param.{}", LABEL_PREFIX, key), value.clone())));
+        labels.extend(self.commit.iter().map(|commit| (String::from("org.opencontainers.image.revision"), commit.clone())));
+        labels.extend(created.iter().map(|created| (String::from("org.opencontainers.image.created"), created.clone())));
+
+        let directives = instructions.iter().take_while(|instruction| is_directive(instruction)).count();
+        let staged = instructions.iter().any(|instruction| matches!(instruction, Instruction::From { .. }));
+
+        let mut output = Vec::with_capacity(instructions.len() + header.len() + 1);
+        for (index, instruction) in instructions.into_iter().enumerate() {
+            if index == directives {
+                output.extend(header.drain(..).map(Instruction::Comment));
+            }
+            output.push(instruction);
+        }
+        output.extend(header.into_iter().map(Instruction::Comment));
+        if staged {
+            output.push(Instruction::Label(labels));
+        }
+        output
+    }
+}
+
+// SHA-256 of the instructions, once rendered, without the provenance itself
+pub fn spec_hash(instructions : &[Instruction]) -> String {
+    let mut hasher = Sha256::new();
+    for instruction in instructions {
+        hasher.update(instruction.to_string().as_bytes());
+        hasher.update(b"\n");
+    }
+    let digest : Vec<String> = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256:{}", digest.concat())
+}
+
+// Check a generated Dockerfile was not edited, by hashing it again without its provenance
+pub fn verify_provenance(source : &str) -> Result<Verification, ParseError> {
+    let instructions = parser::parse(source)?;
+    let label_key = format!("{}{}", LABEL_PREFIX, HASH_KEY);
+    let comment_key = format!("{}: ", HASH_KEY);
+
+    let mut recorded = None;
+    let mut in_header = false;
+    let mut spec = Vec::with_capacity(instructions.len());
+    for instruction in instructions {
+        match &instruction {
+            Instruction::Comment(text) if text.starts_with(HEADER) => {
+                in_header = true;
+                continue;
+            },
+            Instruction::Comment(text) if in_header && is_header_field(text) => {
+                if let Some(hash) = text.strip_prefix(&comment_key) {
+                    recorded = Some(hash.to_string());
+                }
+                continue;
+            },
+            Instruction::Label(pairs) if pairs.iter().any(|(key, _)| *key == label_key) => {
+                if recorded.is_none() {
+                    recorded = pairs.iter().find(|(key, _)| *key == label_key).map(|(_, value)| value.clone());
+                }
+                continue;
+            },
+            _ => in_header = false,
+        }
+        spec.push(instruction);
+    }
+
+    let recorded = match recorded {
+        Some(recorded) => recorded,
+        None => return Ok(Verification::Unrecorded),
+    };
+    let actual = spec_hash(&spec);
+    if actual == recorded {
+        Ok(Verification::Verified)
+    } else {
+        Ok(Verification::Modified { recorded, actual })
+    }
+}
+
+pub fn git_commit(dir : &Path) -> Option<String> {
+    let output = process::Command::new("git").arg("-C").arg(dir).args(["rev-parse", "HEAD"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    if commit.is_empty() { None } else { Some(commit) }
+}
+
+fn is_header_field(text : &str) -> bool {
+    text.split_once(": ").is_some_and(|(key, _)| [HASH_KEY, "git-commit", "created"].contains(&key) || key.starts_with("param."))
+}
+
+// Parser directives, such as `# syntax=docker/dockerfile:1`, must stay the first lines
+fn is_directive(instruction : &Instruction) -> bool {
+    match instruction {
+        Instruction::Comment(text) => text.split_once('=')
+            .is_some_and(|(name, _)| ["syntax", "escape", "check"].contains(&name.trim().to_lowercase().as_str())),
+        _ => false,
+    }
+}
+
+// The current time, or SOURCE_DATE_EPOCH, in RFC 3339
+fn now() -> String {
+    let seconds = env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0));
+    rfc3339(seconds)
+}
+
+fn rfc3339(seconds : u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Civil date from the days since 1970-01-01, in the proleptic Gregorian calendar
+    let shifted = days + 719468;
+    let era = shifted.div_euclid(146097);
+    let day_of_era = shifted.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::provenance::*;
+    use crate::generator::DockerfileGenerator;
+
+    fn generator() -> DockerfileGenerator {
+        let mut generator = DockerfileGenerator::default();
+        generator.comment("syntax=docker/dockerfile:1")
+            .comment("Use an official Python runtime as a parent image")
+            .from("python:3.7-slim")
+            .cmd(r#"["python", "app.py"]"#)
+            .provenance(Provenance::default().param("python_version", 3));
+        generator
+    }
+
+    #[test]
+    fn records_the_spec_after_the_directives() {
+        let rendered = generator().render().unwrap();
+        let lines : Vec<&str> = rendered.lines().collect();
+        let hash = spec_hash(generator().instructions());
+
+        assert_eq!(lines[0], "# syntax=docker/dockerfile:1");
+        assert_eq!(lines[1], format!("# Generated by dock_gen {}", env!("CARGO_PKG_VERSION")));
+        assert_eq!(lines[2], format!("# spec-hash: {}", hash));
+        assert_eq!(lines[3], "# param.python_version: 3");
+        assert_eq!(lines[4], "# Use an official Python runtime as a parent image");
+        assert_eq!(lines[5], "FROM python:3.7-slim");
+        assert_eq!(lines[6], r#"CMD ["python", "app.py"]"#);
+        assert!(lines[7].contains(&format!("io.dock-gen.spec-hash={}", hash)));
+        assert!(lines[7].contains("io.dock-gen.param.python_version=3"));
+        assert!(!rendered.contains("created"));
+    }
+
+    #[test]
+    fn verifies_the_recorded_hash() {
+        let rendered = generator().render().unwrap();
+        assert_eq!(verify_provenance(&rendered).unwrap(), Verification::Verified);
+
+        let edited = rendered.replace("python:3.7-slim", "python:3.8-slim");
+        assert!(matches!(verify_provenance(&edited).unwrap(), Verification::Modified { .. }));
+
+        assert_eq!(verify_provenance("FROM python:3.7-slim\n").unwrap(), Verification::Unrecorded);
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1792413296), "2026-10-19T12:34:56Z");
+    }
+}
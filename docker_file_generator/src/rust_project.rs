@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::Table;
+
+use crate::generator::{exec_form, DockerfileGenerator, GenerateError};
+
+// Where the sources are built, inside the builder stage
+const BUILD_DIR : &str = "/usr/src/app";
+
+const DUMMY_MAIN : &str = "fn main() {}";
+
+// A package of the workspace, as described by its manifest
+#[derive(Clone, Debug, PartialEq)]
+pub struct Package {
+    pub name     : String,
+    // Directory of the package, relative to the workspace root, and empty for the root package
+    pub dir      : PathBuf,
+    pub binaries : Vec<String>,
+    // The files cargo needs to find every target of the package, relative to the package directory
+    pub targets  : Vec<PathBuf>,
+    pub build    : Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Workspace {
+    pub root     : PathBuf,
+    pub packages : Vec<Package>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub builder_image : String,
+    pub runtime_image : String,
+    pub user          : String,
+    // The binary to ship, when the package has more than one
+    pub binary        : Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            builder_image: String::from("rust:1-slim"),
+            runtime_image: String::from("debian:bookworm-slim"),
+            user: String::from("app"),
+            binary: None,
+        }
+    }
+}
+
+impl Workspace {
+    // Read the manifests of the workspace rooted at the directory, without running cargo
+    pub fn load(root : &Path) -> Result<Workspace, GenerateError> {
+        let manifest = read_manifest(&root.join("Cargo.toml"))?;
+        let mut packages = Vec::new();
+
+        if manifest.contains_key("package") {
+            packages.push(Package::load(root, PathBuf::new(), &manifest)?);
+        }
+
+        let members = manifest.get("workspace")
+            .and_then(|workspace| workspace.get("members"))
+            .and_then(|members| members.as_array())
+            .map(|members| members.iter().filter_map(|member| member.as_str()).collect::<Vec<&str>>())
+            .unwrap_or_default();
+        for member in members {
+            for dir in expand_member(root, member)? {
+                let manifest = read_manifest(&root.join(&dir).join("Cargo.toml"))?;
+                packages.push(Package::load(&root.join(&dir), dir, &manifest)?);
+            }
+        }
+
+        if packages.is_empty() {
+            return Err(GenerateError::InvalidArgument(format!("{} has no package", root.join("Cargo.toml").display())));
+        }
+        Ok(Workspace { root: root.to_path_buf(), packages })
+    }
+
+    pub fn package(&self, name : &str) -> Option<&Package> {
+        self.packages.iter().find(|package| package.name == name)
+    }
+
+    // A multi-stage Dockerfile building one binary of the package, and running it in a slim image
+    pub fn dockerfile(&self, package : &str, options : &Options) -> Result<DockerfileGenerator, GenerateError> {
+        let package = self.package(package)
+            .ok_or_else(|| GenerateError::InvalidArgument(format!("No package {} in the workspace", package)))?;
+        let binary = match (&options.binary, package.binaries.as_slice()) {
+            (Some(binary), binaries) if binaries.contains(binary) => binary.clone(),
+            (Some(binary), _) => return Err(GenerateError::InvalidArgument(format!("{} has no binary {}", package.name, binary))),
+            (None, [binary]) => binary.clone(),
+            (None, binaries) if binaries.contains(&package.name) => package.name.clone(),
+            (None, []) => return Err(GenerateError::InvalidArgument(format!("{} has no binary", package.name))),
+            (None, binaries) => return Err(GenerateError::InvalidArgument(
+                format!("{} has several binaries, choose one of {}", package.name, binaries.join(", ")))),
+        };
+
+        // Every package of the workspace needs its targets for cargo to load it
+        let dummies : Vec<String> = self.packages.iter()
+            .flat_map(|member| member.targets.iter().chain(member.build.iter()).map(move |target| unix_path(&member.dir.join(target))))
+            .collect();
+
+        let mut dirs : Vec<String> = dummies.iter()
+            .filter_map(|dummy| dummy.rfind('/').map(|index| dummy[..index].to_string()))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+
+        let mut commands = Vec::new();
+        if !dirs.is_empty() {
+            commands.push(format!("mkdir -p {}", dirs.join(" ")));
+        }
+        for dummy in &dummies {
+            commands.push(format!("echo '{}' > {}", DUMMY_MAIN, dummy));
+        }
+        let build = format!("cargo build --release --package {} --bin {}", package.name, binary);
+        commands.push(build.clone());
+
+        let installed = format!("/usr/local/bin/{}", binary);
+        let mut generator = DockerfileGenerator::default();
+        generator.ignore("target").ignore(".git")
+            .comment("Build stage, the dependencies are built in their own layer, before the sources are copied")
+            .from(&format!("{} AS builder", options.builder_image))
+            .work_dir(BUILD_DIR)
+            .comment("A Cargo.lock is used when there is one")
+            .copy("Cargo.toml Cargo.loc[k]", "./")
+            .for_each(self.packages.iter().filter(|member| member.dir != Path::new("")), |g, member| {
+                let dir = unix_path(&member.dir);
+                g.copy(&format!("{}/Cargo.toml", dir), &format!("{}/", dir))
+            })
+            .run(&commands.join(" && "))
+            .empty_line()
+            .copy(".", ".")
+            .comment("The sources are older than the dummy targets built above, so cargo is told they changed")
+            .run(&format!("touch {} && {}", dummies.join(" "), build))
+            .empty_line()
+            .comment("Runtime stage, with the binary alone")
+            .from(&options.runtime_image)
+            .run(&format!("groupadd --system {user} && useradd --system --gid {user} --no-create-home {user}", user = options.user))
+            .copy(&format!("--from=builder {}/target/release/{}", BUILD_DIR, binary), &installed)
+            .user(&options.user)
+            .entrypoint(&exec_form(&[installed]));
+        Ok(generator)
+    }
+}
+
+impl Package {
+    fn load(dir : &Path, relative : PathBuf, manifest : &Table) -> Result<Package, GenerateError> {
+        let package = manifest.get("package").and_then(|package| package.as_table())
+            .ok_or_else(|| GenerateError::InvalidArgument(format!("{} has no [package]", dir.join("Cargo.toml").display())))?;
+        let name = package.get("name").and_then(|name| name.as_str())
+            .ok_or_else(|| GenerateError::InvalidArgument(format!("{} has no package name", dir.join("Cargo.toml").display())))?
+            .to_string();
+
+        let mut binaries = Vec::new();
+        let mut targets = Vec::new();
+
+        let lib = manifest.get("lib").and_then(|lib| lib.get("path")).and_then(|path| path.as_str());
+        match lib {
+            Some(path) => targets.push(PathBuf::from(path)),
+            None if dir.join("src/lib.rs").is_file() => targets.push(PathBuf::from("src/lib.rs")),
+            None => {},
+        }
+
+        let bins = manifest.get("bin").and_then(|bins| bins.as_array()).cloned().unwrap_or_default();
+        for bin in &bins {
+            let bin_name = bin.get("name").and_then(|name| name.as_str()).unwrap_or(&name).to_string();
+            let path = match bin.get("path").and_then(|path| path.as_str()) {
+                Some(path) => PathBuf::from(path),
+                None if bin_name == name => PathBuf::from("src/main.rs"),
+                None => PathBuf::from(format!("src/bin/{}.rs", bin_name)),
+            };
+            binaries.push(bin_name);
+            targets.push(path);
+        }
+
+        // Targets cargo discovers by itself
+        let autobins = package.get("autobins").and_then(|value| value.as_bool()).unwrap_or(true);
+        if autobins {
+            if dir.join("src/main.rs").is_file() && !targets.contains(&PathBuf::from("src/main.rs")) {
+                binaries.push(name.clone());
+                targets.push(PathBuf::from("src/main.rs"));
+            }
+            let mut discovered = Vec::new();
+            if let Ok(entries) = fs::read_dir(dir.join("src/bin")) {
+                for entry in entries.filter_map(Result::ok) {
+                    let path = entry.path();
+                    let file = entry.file_name().to_string_lossy().into_owned();
+                    if path.is_file() && file.ends_with(".rs") {
+                        discovered.push((file.trim_end_matches(".rs").to_string(), PathBuf::from("src/bin").join(&file)));
+                    } else if path.join("main.rs").is_file() {
+                        discovered.push((file.clone(), PathBuf::from("src/bin").join(&file).join("main.rs")));
+                    }
+                }
+            }
+            discovered.sort();
+            for (bin_name, path) in discovered {
+                if !binaries.contains(&bin_name) && !targets.contains(&path) {
+                    binaries.push(bin_name);
+                    targets.push(path);
+                }
+            }
+        }
+
+        let build = match package.get("build") {
+            Some(toml::Value::String(path)) => Some(PathBuf::from(path)),
+            Some(toml::Value::Boolean(false)) => None,
+            _ if dir.join("build.rs").is_file() => Some(PathBuf::from("build.rs")),
+            _ => None,
+        };
+
+        Ok(Package { name, dir: relative, binaries, targets, build })
+    }
+}
+
+fn read_manifest(path : &Path) -> Result<Table, GenerateError> {
+    let content = fs::read_to_string(path).map_err(GenerateError::IO)?;
+    content.parse::<Table>().map_err(|error| GenerateError::InvalidArgument(format!("{}: {}", path.display(), error)))
+}
+
+// Members are directories, or a directory ending with /* for all the packages it contains
+fn expand_member(root : &Path, member : &str) -> Result<Vec<PathBuf>, GenerateError> {
+    let parent = match member.strip_suffix("/*") {
+        Some(parent) => parent,
+        None => return Ok(vec![PathBuf::from(member)]),
+    };
+
+    let mut dirs : Vec<PathBuf> = fs::read_dir(root.join(parent)).map_err(GenerateError::IO)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().join("Cargo.toml").is_file())
+        .map(|entry| Path::new(parent).join(entry.file_name()))
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+// Paths inside the image always use forward slashes
+fn unix_path(path : &Path) -> String {
+    path.components().map(|component| component.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rust_project::*;
+
+    fn workspace() -> Workspace {
+        Workspace::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("..")).unwrap()
+    }
+
+    #[test]
+    fn reads_the_workspace_manifests() {
+        let workspace = workspace();
+        let names : Vec<&str> = workspace.packages.iter().map(|package| package.name.as_str()).collect();
+        assert_eq!(names, vec!["dock_gen", "dock_gen_macros", "list", "overload_test", "closures_playground"]);
+
+        let dock_gen = workspace.package("dock_gen").unwrap();
+        assert_eq!(dock_gen.binaries, vec!["dock-gen"]);
+        assert_eq!(dock_gen.targets, vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/bin/dock-gen.rs")]);
+        assert_eq!(workspace.package("overload_test").unwrap().binaries, vec!["overload_test"]);
+    }
+
+    #[test]
+    fn builds_the_chosen_binary() {
+        let generator = workspace().dockerfile("overload_test", &Options::default()).unwrap();
+        let dockerfile = generator.to_string();
+
+        assert!(dockerfile.contains("COPY closures_playground/Cargo.toml closures_playground/\r\n"));
+        assert!(dockerfile.contains("echo 'fn main() {}' > overload_test/src/main.rs"));
+        assert!(dockerfile.contains("COPY --from=builder /usr/src/app/target/release/overload_test /usr/local/bin/overload_test\r\n\
+                                     USER app\r\n\
+                                     ENTRYPOINT [\"/usr/local/bin/overload_test\"]\r\n"));
+
+        assert!(workspace().dockerfile("list", &Options::default()).is_err());
+        assert!(workspace().dockerfile("missing", &Options::default()).is_err());
+    }
+}
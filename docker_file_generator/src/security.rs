@@ -0,0 +1,402 @@
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::instruction::{self, Command, Instruction};
+use crate::parser::{self, ParseError};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    HardcodedSecret,
+    CurlPipeShell,
+    RemoteAddWithoutChecksum,
+    InsecureTransport,
+    WorldWritable,
+    MissingUser,
+    SensitiveVolume,
+    RootAfterUser,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub rule        : Rule,
+    pub level       : Level,
+    pub message     : String,
+    // Index of the offending instruction, and its line in the Dockerfile
+    pub instruction : usize,
+    pub line        : usize,
+}
+
+const RULES : [Rule; 8] = [Rule::HardcodedSecret, Rule::CurlPipeShell, Rule::RemoteAddWithoutChecksum, Rule::InsecureTransport,
+                           Rule::WorldWritable, Rule::MissingUser, Rule::SensitiveVolume, Rule::RootAfterUser];
+
+const SECRET_NAMES : [&str; 10] = ["PASSWORD", "PASSWD", "SECRET", "TOKEN", "API_KEY", "APIKEY", "ACCESS_KEY", "PRIVATE_KEY", "CREDENTIAL", "CREDENTIALS"];
+
+const TOKEN_PREFIXES : [&str; 10] = ["ghp_", "gho_", "ghs_", "ghu_", "github_pat_", "glpat-", "xoxb-", "xoxp-", "sk_live_", "npm_"];
+
+const INSECURE_FLAGS : [&str; 6] = ["--trusted-host", "--insecure", "--no-check-certificate", "--allow-unauthenticated",
+                                    "--allow-insecure-repositories", "--disable-tls"];
+
+const INSECURE_ENV : [&str; 4] = ["PIP_TRUSTED_HOST", "NODE_TLS_REJECT_UNAUTHORIZED", "GIT_SSL_NO_VERIFY", "PYTHONHTTPSVERIFY"];
+
+// Commands which fail, or do nothing useful, without root
+const ROOT_COMMANDS : [&str; 16] = ["apt-get", "apt", "dpkg", "apk", "dnf", "microdnf", "yum", "rpm", "useradd", "groupadd", "adduser",
+                                   "addgroup", "usermod", "chown", "chgrp", "update-ca-certificates"];
+
+const SENSITIVE_PATHS : [&str; 11] = ["/", "/etc", "/root", "/home", "/boot", "/proc", "/sys", "/dev", "/run", "/var/run", "/var/lib/docker"];
+
+impl Rule {
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::HardcodedSecret => "DG001",
+            Rule::CurlPipeShell => "DG002",
+            Rule::RemoteAddWithoutChecksum => "DG003",
+            Rule::InsecureTransport => "DG004",
+            Rule::WorldWritable => "DG005",
+            Rule::MissingUser => "DG006",
+            Rule::SensitiveVolume => "DG007",
+            Rule::RootAfterUser => "DG008",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::HardcodedSecret => "Credentials are written into the image and its history",
+            Rule::CurlPipeShell => "A remote script is piped into a shell without verification",
+            Rule::RemoteAddWithoutChecksum => "A remote file is added without verifying its checksum",
+            Rule::InsecureTransport => "TLS verification or package authentication is disabled",
+            Rule::WorldWritable => "Files are made writable by every user",
+            Rule::MissingUser => "The container runs as root",
+            Rule::SensitiveVolume => "A sensitive system path is declared as a volume",
+            Rule::RootAfterUser => "A step after the switch to a non-root USER needs root",
+        }
+    }
+
+    fn level(self) -> Level {
+        match self {
+            Rule::HardcodedSecret => Level::Error,
+            _ => Level::Warning,
+        }
+    }
+}
+
+// Scan an instruction model, generated or parsed. Lines are those of the rendered Dockerfile.
+pub fn scan(instructions : &[Instruction]) -> Vec<Finding> {
+    let lines = instruction::rendered_lines(instructions);
+    let mut findings = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        check(instruction, &mut |rule, message| findings.push(Finding { rule, level: rule.level(), message, instruction: index, line: lines[index] }));
+    }
+
+    for (index, message) in needs_root(instructions) {
+        findings.push(Finding { rule: Rule::RootAfterUser, level: Rule::RootAfterUser.level(), message, instruction: index, line: lines[index] });
+    }
+    if let Some((index, message)) = runs_as_root(instructions) {
+        findings.push(Finding { rule: Rule::MissingUser, level: Rule::MissingUser.level(), message, instruction: index, line: lines[index] });
+    }
+    findings
+}
+
+// Scan a Dockerfile's source, reporting the lines as written in it
+pub fn scan_source(source : &str) -> Result<Vec<Finding>, ParseError> {
+    let parsed = parser::parse_with_lines(source)?;
+    let instructions : Vec<Instruction> = parsed.iter().map(|(_, instruction)| instruction.clone()).collect();
+
+    let mut findings = scan(&instructions);
+    for finding in &mut findings {
+        finding.line = parsed[finding.instruction].0;
+    }
+    Ok(findings)
+}
+
+fn check(instruction : &Instruction, report : &mut dyn FnMut(Rule, String)) {
+    match instruction {
+        Instruction::Env(pairs) | Instruction::Label(pairs) => {
+            for (key, value) in pairs {
+                if secret_assignment(key, value) || token_like(value) {
+                    report(Rule::HardcodedSecret, format!("{} sets {} to a hard-coded credential", instruction.keyword().unwrap(), key));
+                }
+                if INSECURE_ENV.contains(&key.to_uppercase().as_str()) {
+                    report(Rule::InsecureTransport, format!("ENV {} weakens TLS verification", key));
+                }
+            }
+        },
+        Instruction::Arg { name, default: Some(default) } if secret_assignment(name, default) || token_like(default) => {
+            report(Rule::HardcodedSecret, format!("ARG {} has a hard-coded credential as its default", name));
+        },
+        Instruction::Run { command, .. } => {
+            let line = match command {
+                Command::Shell(line) => line.clone(),
+                Command::Exec(args) => args.join(" "),
+            };
+            check_run(&line, report);
+        },
+        Instruction::Add { flags, sources, .. } | Instruction::Copy { flags, sources, .. } => {
+            let remote = sources.iter().find(|source| source.starts_with("http://") || source.starts_with("https://"));
+            if let (Instruction::Add { .. }, Some(remote)) = (instruction, remote) {
+                if !flags.iter().any(|flag| flag.name == "checksum") {
+                    report(Rule::RemoteAddWithoutChecksum, format!("ADD {} without --checksum", remote));
+                }
+            }
+            if let Some(mode) = instruction.flag("chmod").and_then(|flag| flag.value.as_ref()) {
+                if world_writable(mode) {
+                    report(Rule::WorldWritable, format!("{} --chmod={} makes the files world-writable", instruction.keyword().unwrap(), mode));
+                }
+            }
+        },
+        Instruction::Volume(volumes) => {
+            for volume in volumes {
+                let path = if volume.len() > 1 { volume.trim_end_matches('/') } else { volume.as_str() };
+                let sensitive = SENSITIVE_PATHS.contains(&path)
+                    || path.ends_with("docker.sock")
+                    || ["/proc/", "/sys/", "/dev/", "/etc/"].iter().any(|prefix| path.starts_with(prefix));
+                if sensitive {
+                    report(Rule::SensitiveVolume, format!("VOLUME {} exposes a sensitive system path", volume));
+                }
+            }
+        },
+        Instruction::OnBuild(inner) => check(inner, report),
+        _ => {},
+    }
+}
+
+fn check_run(line : &str, report : &mut dyn FnMut(Rule, String)) {
+    let words : Vec<&str> = line.split_whitespace().collect();
+
+    for (index, word) in words.iter().enumerate() {
+        let (key, value) = match word.find('=') {
+            Some(position) => (word[..position].trim_start_matches('-'), Some(&word[position + 1..])),
+            None => (word.trim_start_matches('-'), words.get(index + 1).cloned().filter(|_| word.starts_with("--"))),
+        };
+        let secret = match value {
+            Some(value) => secret_assignment(key, value.trim_matches(|c| c == '"' || c == '\'')),
+            None => false,
+        };
+        if secret || token_like(word) {
+            report(Rule::HardcodedSecret, format!("RUN passes a hard-coded credential ({})", key));
+            break;
+        }
+    }
+
+    for flag in INSECURE_FLAGS.iter() {
+        if words.iter().any(|word| word == flag || word.starts_with(&format!("{}=", flag))) {
+            report(Rule::InsecureTransport, format!("RUN uses {}", flag));
+        }
+    }
+    if words.contains(&"curl") && words.contains(&"-k") {
+        report(Rule::InsecureTransport, String::from("RUN uses curl -k"));
+    }
+
+    let segments : Vec<&str> = line.split('|').collect();
+    for pair in segments.windows(2) {
+        let fetches = pair[0].contains("curl ") || pair[0].contains("wget ");
+        let shell = pair[1].split_whitespace().find(|word| *word != "sudo" && !word.starts_with('-'));
+        if fetches && shell.is_some_and(|shell| ["sh", "bash", "zsh", "ash", "dash", "ksh"].contains(&shell.trim_start_matches("/bin/"))) {
+            report(Rule::CurlPipeShell, format!("RUN pipes a download into {}", shell.unwrap()));
+        }
+    }
+
+    if let Some(chmod) = words.iter().position(|word| *word == "chmod") {
+        let mode = words[chmod + 1..].iter().find(|word| !word.starts_with('-'));
+        if let Some(mode) = mode.filter(|mode| world_writable(mode)) {
+            report(Rule::WorldWritable, format!("RUN chmod {} makes the files world-writable", mode));
+        }
+    }
+}
+
+fn secret_assignment(key : &str, value : &str) -> bool {
+    !value.is_empty() && !value.starts_with('$') && !value.starts_with('/') && secret_name(key)
+}
+
+// The secret names are whole parts of the key, as in GITHUB_TOKEN or DB_PASSWORD, not TOKENIZERS_PARALLELISM
+fn secret_name(key : &str) -> bool {
+    let key = key.to_uppercase();
+    let parts : Vec<&str> = key.split(['_', '-', '.']).collect();
+    SECRET_NAMES.iter().any(|name| {
+        let name : Vec<&str> = name.split('_').collect();
+        parts.windows(name.len()).any(|window| window == name.as_slice())
+    })
+}
+
+fn token_like(value : &str) -> bool {
+    let value = value.trim_matches(|c| c == '"' || c == '\'');
+    let prefixed = TOKEN_PREFIXES.iter().any(|prefix| value.starts_with(prefix) && value.len() >= prefix.len() + 16);
+    let aws = value.len() == 20 && value.starts_with("AKIA") && value.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+    prefixed || aws || value.contains("-----BEGIN") && value.contains("PRIVATE KEY")
+}
+
+fn world_writable(mode : &str) -> bool {
+    if mode.chars().all(|c| c.is_digit(8)) {
+        return mode.chars().last().and_then(|c| c.to_digit(8)).is_some_and(|others| others & 2 != 0);
+    }
+    mode.split(',').any(|clause| match clause.find(['+', '=']) {
+        Some(operator) => clause[..operator].contains(['o', 'a']) && clause[operator + 1..].contains('w'),
+        None => false,
+    })
+}
+
+fn is_root(user : &str) -> bool {
+    ["root", "0"].contains(&user.split(':').next().unwrap_or(user))
+}
+
+// Steps which need root while a stage runs as another user: package installs, user management and ownership changes.
+// Ports below 1024 are not among them, since Docker 20.10 lets unprivileged processes bind them.
+fn needs_root(instructions : &[Instruction]) -> Vec<(usize, String)> {
+    let mut user : Option<&str> = None;
+    let mut steps = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let current = match instruction {
+            Instruction::From { .. } => {
+                user = None;
+                continue;
+            },
+            Instruction::User(name) => {
+                user = Some(name.as_str()).filter(|name| !is_root(name));
+                continue;
+            },
+            _ => match user {
+                Some(user) => user,
+                None => continue,
+            },
+        };
+
+        if let Instruction::Run { command, .. } = instruction {
+            let line = match command {
+                Command::Shell(line) => line.clone(),
+                Command::Exec(args) => args.join(" "),
+            };
+            let mut commands : Vec<&str> = line.split(['&', '|', ';'])
+                .filter_map(|segment| segment.split_whitespace().find(|word| !word.contains('=')))
+                .map(|word| word.rsplit('/').next().unwrap_or(word))
+                .filter(|command| ROOT_COMMANDS.contains(command))
+                .collect();
+            commands.dedup();
+            for command in commands {
+                steps.push((index, format!("RUN {} needs root, but runs as USER {}", command, current)));
+            }
+        }
+    }
+    steps
+}
+
+// The final stage should switch to a user other than root
+fn runs_as_root(instructions : &[Instruction]) -> Option<(usize, String)> {
+    let stages = instruction::stages(instructions);
+    let last = stages.last()?;
+    let user = last.steps().filter_map(|step| match step {
+        Instruction::User(user) => Some(user.as_str()),
+        _ => None,
+    }).last();
+
+    let from = instructions.iter().rposition(|instruction| matches!(instruction, Instruction::From { .. }))?;
+
+    match user {
+        None => Some((from, format!("the final stage, based on {}, never sets USER", last.image))),
+        Some(user) if is_root(user) => Some((from, format!("the final stage switches to USER {}", user))),
+        Some(_) => None,
+    }
+}
+
+// A SARIF-like log, for code scanning tools
+pub fn to_sarif(findings : &[Finding], artifact : &str) -> String {
+    let rules : Vec<serde_json::Value> = RULES.iter().map(|rule| json!({
+        "id": rule.id(),
+        "name": rule,
+        "shortDescription": { "text": rule.description() },
+    })).collect();
+
+    let results : Vec<serde_json::Value> = findings.iter().map(|finding| json!({
+        "ruleId": finding.rule.id(),
+        "level": finding.level,
+        "message": { "text": finding.message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": artifact },
+                "region": { "startLine": finding.line },
+            },
+        }],
+    })).collect();
+
+    let log = json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": { "driver": { "name": "dock_gen", "rules": rules } },
+            "results": results,
+        }],
+    });
+    serde_json::to_string_pretty(&log).expect("a SARIF log is always serializable")
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        write!(f, "line {}: {} {}: {}", self.line, level, self.rule.id(), self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::security::*;
+
+    fn rules(source : &str) -> Vec<Rule> {
+        scan_source(source).unwrap().iter().map(|finding| finding.rule).collect()
+    }
+
+    #[test]
+    fn flags_each_rule() {
+        assert_eq!(rules("FROM alpine\nENV DB_PASSWORD=hunter2\nUSER app\n"), vec![Rule::HardcodedSecret]);
+        assert_eq!(rules("FROM alpine\nENV GITHUB_TOKEN=abc AWS_SECRET_ACCESS_KEY=abc npm.apiKey=abc\nUSER app\n"), vec![Rule::HardcodedSecret; 3]);
+        assert_eq!(rules("FROM alpine\nRUN curl -sSL https://get.example.com | sh\nUSER app\n"), vec![Rule::CurlPipeShell]);
+        assert_eq!(rules("FROM alpine\nADD https://example.com/app.tgz /app/\nUSER app\n"), vec![Rule::RemoteAddWithoutChecksum]);
+        assert_eq!(rules("FROM alpine\nRUN chmod -R 777 /app\nUSER app\n"), vec![Rule::WorldWritable]);
+        assert_eq!(rules("FROM alpine\nVOLUME /var/run/docker.sock\nUSER app\n"), vec![Rule::SensitiveVolume]);
+        assert_eq!(rules("FROM alpine\nUSER root\n"), vec![Rule::MissingUser]);
+    }
+
+    #[test]
+    fn flags_root_steps_after_user() {
+        let findings = scan_source("FROM debian\nRUN apt-get update\nUSER app\nRUN FOO=1 apt-get install -y curl && /bin/chown app /app\n\
+                                    EXPOSE 80 8080\nUSER root\nRUN apk add git\nUSER app\n").unwrap();
+        assert_eq!(findings.iter().map(|finding| (finding.rule, finding.line)).collect::<Vec<_>>(),
+                   vec![(Rule::RootAfterUser, 4), (Rule::RootAfterUser, 4)]);
+        assert_eq!(findings[1].message, "RUN chown needs root, but runs as USER app");
+    }
+
+    #[test]
+    fn ignores_safe_instructions() {
+        assert!(rules("FROM alpine\nARG TOKEN\nENV TOKEN=$TOKEN\nADD --checksum=sha256:abc https://example.com/a /a\nRUN chmod 755 /app\nUSER app\n").is_empty());
+        assert!(rules("FROM python:3.12\nENV TOKENIZERS_PARALLELISM=false PASSWORDLESS=1 SECRETARY=bob\nUSER app\n").is_empty());
+    }
+
+    #[test]
+    fn reports_the_rendered_lines() {
+        let instructions = crate::parser::parse("FROM alpine\nRUN sh <<EOF\necho a\necho b\nEOF\nENV DB_PASSWORD=hunter2\nUSER app\n").unwrap();
+        assert_eq!(scan(&instructions).iter().map(|finding| (finding.rule, finding.line)).collect::<Vec<_>>(), vec![(Rule::HardcodedSecret, 6)]);
+    }
+
+    #[test]
+    fn reports_example_lines_as_sarif() {
+        let source = "FROM python:2.7-slim\nWORKDIR /app\n\n# Install any needed packages specified in requirements.txt\n\
+                      RUN pip install --trusted-host pypi.python.org \\\n    -r requirements.txt\n";
+        let findings = scan_source(source).unwrap();
+        assert_eq!(findings.iter().map(|finding| (finding.rule, finding.line)).collect::<Vec<_>>(),
+                   vec![(Rule::InsecureTransport, 5), (Rule::MissingUser, 1)]);
+
+        let sarif : serde_json::Value = serde_json::from_str(&to_sarif(&findings, "Dockerfile")).unwrap();
+        assert_eq!(sarif["runs"][0]["results"][0]["ruleId"], "DG004");
+        assert_eq!(sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"]["region"]["startLine"], 5);
+    }
+}
@@ -0,0 +1,512 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::generator::GenerateError;
+use crate::instruction::{self, Command, Instruction};
+use crate::packages::PackageManager;
+use crate::policy::ImageRef;
+
+const MB : u64 = 1_000_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Libc {
+    Glibc,
+    Musl,
+    // Only static binaries run, as on distroless/static or scratch
+    None,
+}
+
+// What the table knows of a base image
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BaseImage {
+    // A reference without a tag stands for all its tags
+    pub image   : String,
+    // Uncompressed, as `docker images` shows it
+    pub size    : u64,
+    pub libc    : Libc,
+    // Images with a shell come with the package manager of their distribution
+    pub shell   : bool,
+    // The language runtime it ships, such as "python 3.7"
+    pub runtime : Option<String>,
+}
+
+// Sizes known offline, of base images and of the system packages installed on top of them. The defaults are
+// approximations of the images on Docker Hub, which a TOML file can extend or override:
+//
+// ```toml
+// [images."python:3.11-slim"]
+// size_mb = 131
+// libc = "glibc"
+// runtime = "python 3.11"
+//
+// [packages]
+// libpq5 = 1.0
+// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeTable {
+    pub images   : Vec<BaseImage>,
+    // Installed sizes of the packages, with their dependencies on a slim image
+    pub packages : Vec<(String, u64)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageEntry {
+    size_mb : f64,
+    libc    : Libc,
+    #[serde(default = "has_shell")]
+    shell   : bool,
+    runtime : Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TableFile {
+    #[serde(default)]
+    images   : toml::Table,
+    #[serde(default)]
+    packages : toml::Table,
+}
+
+fn has_shell() -> bool {
+    true
+}
+
+// The estimated size of the image built on a base
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Estimate {
+    pub image    : String,
+    // None when the base image is not in the table
+    pub base     : Option<u64>,
+    // The system packages the final stage keeps, with their size in the table
+    pub packages : Vec<(String, u64)>,
+    // Lines of the layers whose size is not known offline: copies, language packages and packages missing from the table
+    pub unknown  : Vec<usize>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub estimate : Estimate,
+    // What would break, or change, when building on this base instead of the current one
+    pub notes    : Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub current    : Estimate,
+    pub candidates : Vec<Candidate>,
+}
+
+impl Default for SizeTable {
+    fn default() -> SizeTable {
+        let image = |image : &str, size : u64, libc : Libc, shell : bool, runtime : Option<&str>| BaseImage {
+            image: image.to_string(),
+            size: size * MB,
+            libc,
+            shell,
+            runtime: runtime.map(String::from),
+        };
+        let images = vec![
+            image("scratch", 0, Libc::None, false, None),
+            image("alpine", 7, Libc::Musl, true, None),
+            image("debian:bookworm", 117, Libc::Glibc, true, None),
+            image("debian:bookworm-slim", 75, Libc::Glibc, true, None),
+            image("ubuntu:22.04", 78, Libc::Glibc, true, None),
+            image("python:2.7", 902, Libc::Glibc, true, Some("python 2.7")),
+            image("python:2.7-slim", 148, Libc::Glibc, true, Some("python 2.7")),
+            image("python:2.7-alpine", 71, Libc::Musl, true, Some("python 2.7")),
+            image("python:3.7", 907, Libc::Glibc, true, Some("python 3.7")),
+            image("python:3.7-slim", 125, Libc::Glibc, true, Some("python 3.7")),
+            image("python:3.7-alpine", 48, Libc::Musl, true, Some("python 3.7")),
+            image("python:3.12", 1020, Libc::Glibc, true, Some("python 3.12")),
+            image("python:3.12-slim", 130, Libc::Glibc, true, Some("python 3.12")),
+            image("python:3.12-alpine", 57, Libc::Musl, true, Some("python 3.12")),
+            image("node:20", 1100, Libc::Glibc, true, Some("node 20")),
+            image("node:20-slim", 200, Libc::Glibc, true, Some("node 20")),
+            image("node:20-alpine", 135, Libc::Musl, true, Some("node 20")),
+            image("gcr.io/distroless/static", 2, Libc::None, false, None),
+            image("gcr.io/distroless/base", 20, Libc::Glibc, false, None),
+            image("gcr.io/distroless/cc", 23, Libc::Glibc, false, None),
+            image("gcr.io/distroless/python3", 53, Libc::Glibc, false, Some("python 3.11")),
+            image("gcr.io/distroless/python3-debian12", 53, Libc::Glibc, false, Some("python 3.11")),
+        ];
+
+        let packages = [("build-essential", 250), ("ca-certificates", 1), ("curl", 6), ("g++", 60), ("gcc", 90), ("gdb", 40),
+                        ("git", 35), ("libpq-dev", 15), ("make", 1), ("musl-dev", 10), ("procps", 2), ("strace", 2), ("wget", 3)];
+        SizeTable {
+            images,
+            packages: packages.iter().map(|(name, size)| (name.to_string(), size * MB)).collect(),
+        }
+    }
+}
+
+impl SizeTable {
+    // The default table, extended by the file
+    pub fn load(path : &Path) -> Result<SizeTable, GenerateError> {
+        let content = fs::read_to_string(path).map_err(GenerateError::IO)?;
+        let mut table = SizeTable::default();
+        table.extend_toml(&content).map_err(|reason| GenerateError::InvalidArgument(format!("{}: {}", path.display(), reason)))?;
+        Ok(table)
+    }
+
+    pub fn extend_toml(&mut self, content : &str) -> Result<(), String> {
+        let file : TableFile = toml::from_str(content).map_err(|error| error.to_string())?;
+        for (image, entry) in file.images {
+            let entry : ImageEntry = entry.try_into().map_err(|error| format!("[images] {}: {}", image, error))?;
+            self.image(BaseImage { image, size: (entry.size_mb * MB as f64) as u64, libc: entry.libc, shell: entry.shell, runtime: entry.runtime });
+        }
+        for (name, size) in file.packages {
+            let size = match size {
+                toml::Value::Float(size) => size,
+                toml::Value::Integer(size) => size as f64,
+                _ => return Err(format!("[packages] {} must be a size in MB", name)),
+            };
+            self.package(&name, (size * MB as f64) as u64);
+        }
+        Ok(())
+    }
+
+    // Add an image, replacing the one with the same reference
+    pub fn image(&mut self, image : BaseImage) -> &mut SizeTable {
+        self.images.retain(|known| known.image != image.image);
+        self.images.push(image);
+        self
+    }
+
+    pub fn package(&mut self, name : &str, size : u64) -> &mut SizeTable {
+        self.packages.retain(|(known, _)| known != name);
+        self.packages.push((name.to_string(), size));
+        self
+    }
+
+    // The entry for the image. One naming the tag wins over one for the whole image.
+    pub fn find(&self, image : &str) -> Option<&BaseImage> {
+        let reference = ImageRef::parse(image);
+        self.images.iter()
+            .map(|known| (known, ImageRef::parse(&known.image)))
+            .filter(|(_, pattern)| reference.matches(pattern))
+            .max_by_key(|(_, pattern)| pattern.tag.is_some())
+            .map(|(known, _)| known)
+    }
+
+    fn package_size(&self, name : &str) -> Option<u64> {
+        self.packages.iter().find(|(known, _)| known == name).map(|(_, size)| *size)
+    }
+}
+
+// The size of the image the Dockerfile builds: the base of its final stage, and the packages the stage installs
+pub fn estimate(instructions : &[Instruction], table : &SizeTable) -> Estimate {
+    estimate_on(instructions, &instruction::rendered_lines(instructions), base_image(instructions), table)
+}
+
+// The estimate of the Dockerfile on each of the candidate base images, with what they would change
+pub fn compare(instructions : &[Instruction], candidates : &[&str], table : &SizeTable) -> Comparison {
+    compare_at(instructions, &instruction::rendered_lines(instructions), candidates, table)
+}
+
+// Compare the instructions as parsed with their lines, reporting the lines as written in the Dockerfile
+pub fn compare_with_lines(parsed : &[(usize, Instruction)], candidates : &[&str], table : &SizeTable) -> Comparison {
+    let (lines, instructions) : (Vec<usize>, Vec<Instruction>) = parsed.iter().cloned().unzip();
+    compare_at(&instructions, &lines, candidates, table)
+}
+
+// The image the final stage is built on
+fn base_image(instructions : &[Instruction]) -> &str {
+    instruction::base_image(instructions).unwrap_or("scratch")
+}
+
+fn compare_at(instructions : &[Instruction], lines : &[usize], candidates : &[&str], table : &SizeTable) -> Comparison {
+    let current = estimate_on(instructions, lines, base_image(instructions), table);
+    let base = table.find(&current.image);
+    let candidates = candidates.iter()
+        .map(|candidate| Candidate {
+            estimate: estimate_on(instructions, lines, candidate, table),
+            notes: notes(instructions, lines, base, candidate, table),
+        })
+        .collect();
+    Comparison { current, candidates }
+}
+
+// The positions of the instructions of the final stage and of the stages it is built on, the first base first
+fn stage_positions(instructions : &[Instruction]) -> Vec<usize> {
+    let froms : Vec<usize> = instructions.iter().enumerate()
+        .filter(|(_, instruction)| matches!(instruction, Instruction::From { .. }))
+        .map(|(index, _)| index)
+        .collect();
+    let stages = instruction::stages(instructions);
+    instruction::ancestry(&stages).iter().rev()
+        .flat_map(|stage| froms[stage.index] + 1..froms.get(stage.index + 1).copied().unwrap_or(instructions.len()))
+        .collect()
+}
+
+fn estimate_on(instructions : &[Instruction], lines : &[usize], image : &str, table : &SizeTable) -> Estimate {
+    let mut estimate = Estimate { image: image.to_string(), base: table.find(image).map(|base| base.size), packages: Vec::new(), unknown: Vec::new() };
+
+    for index in stage_positions(instructions) {
+        match &instructions[index] {
+            Instruction::Run { command, .. } => {
+                let (packages, other) = installs(&command.to_string());
+                let mut known = true;
+                for package in packages {
+                    match table.package_size(&package) {
+                        Some(size) => estimate.packages.push((package, size)),
+                        None => known = false,
+                    }
+                }
+                if other || !known {
+                    estimate.unknown.push(lines[index]);
+                }
+            },
+            Instruction::Copy { heredocs, .. } | Instruction::Add { heredocs, .. } if heredocs.is_empty() => estimate.unknown.push(lines[index]),
+            _ => {},
+        }
+    }
+    estimate
+}
+
+fn notes(instructions : &[Instruction], lines : &[usize], current : Option<&BaseImage>, candidate : &str, table : &SizeTable) -> Vec<String> {
+    let image = match table.find(candidate) {
+        Some(image) => image,
+        None => return vec![String::from("not in the size table")],
+    };
+    let stage : Vec<(usize, &Instruction)> = stage_positions(instructions).into_iter().map(|index| (lines[index], &instructions[index])).collect();
+    let mut notes = Vec::new();
+
+    match current {
+        None => notes.push(String::from("libc change unknown: the current base image is not in the size table")),
+        Some(current) if current.libc != image.libc => notes.push(match image.libc {
+            Libc::Musl => String::from("musl instead of glibc: manylinux wheels and glibc binaries do not run, C extensions are built from source"),
+            Libc::Glibc => String::from("glibc instead of musl: packages built for Alpine must be reinstalled"),
+            Libc::None => String::from("no libc: only static binaries run"),
+        }),
+        Some(_) => {},
+    }
+
+    if let (Some(current), Some(runtime)) = (current.and_then(|current| current.runtime.as_ref()), image.runtime.as_ref()) {
+        if current != runtime {
+            notes.push(format!("{} instead of {}", runtime, current));
+        }
+    }
+
+    let runs : Vec<usize> = stage.iter().filter(|(_, instruction)| matches!(instruction, Instruction::Run { .. })).map(|(line, _)| *line).collect();
+    if !image.shell {
+        let shell_form = stage.iter()
+            .filter(|(_, instruction)| matches!(instruction,
+                Instruction::Cmd(Command::Shell(_)) | Instruction::Entrypoint(Command::Shell(_))
+                    | Instruction::Healthcheck { command: Some(Command::Shell(_)), .. }))
+            .map(|(line, _)| *line);
+        let steps : Vec<usize> = runs.iter().copied().chain(shell_form).collect();
+        if !steps.is_empty() {
+            notes.push(format!("no shell nor package manager: the steps at {} can not run", line_numbers(&steps)));
+        }
+    } else if current.is_some_and(|current| current.shell) {
+        let before = PackageManager::for_stage(instructions);
+        let after = PackageManager::detect(candidate);
+        let installing : Vec<usize> = stage.iter()
+            .filter(|(_, instruction)| match instruction {
+                Instruction::Run { command, .. } => !installs(&command.to_string()).0.is_empty(),
+                _ => false,
+            })
+            .map(|(line, _)| *line)
+            .collect();
+        if before != after && !installing.is_empty() {
+            notes.push(format!("the installs at {} use {}, the image has {}", line_numbers(&installing), before.program(), after.program()));
+        }
+    }
+    notes
+}
+
+// The system packages a RUN installs and keeps, and whether it installs something else, such as language packages
+fn installs(command : &str) -> (Vec<String>, bool) {
+    let mut installed : Vec<(String, Option<String>)> = Vec::new();
+    let mut removed = Vec::new();
+    let mut other = false;
+
+    for segment in command.split("&&").flat_map(|part| part.split(';')) {
+        let words : Vec<&str> = segment.split_whitespace().collect();
+        let (program, action) = match words.as_slice() {
+            [program, action, ..] => (*program, *action),
+            _ => continue,
+        };
+        let arguments = &words[2..];
+        match (program, action) {
+            ("apt-get", "install") | ("apt", "install") | ("apk", "add") | ("dnf", "install") | ("microdnf", "install") | ("yum", "install") => {
+                let mut group = None;
+                let mut arguments = arguments.iter();
+                while let Some(argument) = arguments.next() {
+                    if *argument == "--virtual" || *argument == "-t" {
+                        group = arguments.next().map(|name| name.to_string());
+                    } else if !argument.starts_with('-') {
+                        let name = argument.split('=').next().unwrap_or(argument);
+                        installed.push((name.to_string(), group.clone()));
+                    }
+                }
+            },
+            ("apt-get", "purge") | ("apt-get", "remove") | ("apt", "purge") | ("apt", "remove") | ("apk", "del") | ("dnf", "remove") | ("microdnf", "remove") | ("yum", "remove") => {
+                removed.extend(arguments.iter().filter(|argument| !argument.starts_with('-')).map(|argument| argument.to_string()));
+            },
+            ("pip", "install") | ("pip3", "install") | ("npm", "install") | ("npm", "ci") | ("yarn", "install") | ("cargo", "install")
+                | ("poetry", "install") | ("bundle", "install") | ("go", "install") => other = true,
+            _ => {},
+        }
+    }
+
+    let kept = installed.into_iter()
+        .filter(|(name, group)| !removed.contains(name) && !group.as_ref().is_some_and(|group| removed.contains(group)))
+        .map(|(name, _)| name)
+        .collect();
+    (kept, other)
+}
+
+fn line_numbers(lines : &[usize]) -> String {
+    let numbers : Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    match numbers.len() {
+        1 => format!("line {}", numbers[0]),
+        _ => format!("lines {}", numbers.join(", ")),
+    }
+}
+
+fn format_size(size : u64) -> String {
+    if size >= 10 * MB || size == 0 {
+        format!("{} MB", (size + MB / 2) / MB)
+    } else {
+        format!("{:.1} MB", size as f64 / MB as f64)
+    }
+}
+
+impl Estimate {
+    // The size known offline, None when the base image is not in the table
+    pub fn total(&self) -> Option<u64> {
+        self.base.map(|base| base + self.packages.iter().map(|(_, size)| size).sum::<u64>())
+    }
+}
+
+impl Comparison {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a comparison is always serializable")
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.total() {
+            Some(total) => write!(f, "{}: {}", self.image, format_size(total))?,
+            None => write!(f, "{}: unknown size", self.image)?,
+        }
+        if let Some(base) = self.base {
+            if !self.packages.is_empty() {
+                let packages : Vec<&str> = self.packages.iter().map(|(name, _)| name.as_str()).collect();
+                write!(f, " ({} base, {} for {})", format_size(base), format_size(self.total().unwrap_or(base) - base), packages.join(", "))?;
+            }
+        }
+        if !self.unknown.is_empty() {
+            write!(f, ", plus the layers at {}", line_numbers(&self.unknown))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "current  {}", self.current)?;
+        for candidate in &self.candidates {
+            write!(f, "instead  {}", candidate.estimate)?;
+            if let (Some(current), Some(size)) = (self.current.total(), candidate.estimate.total()) {
+                let (sign, difference) = if size >= current { ('+', size - current) } else { ('-', current - size) };
+                write!(f, " [{}{}]", sign, format_size(difference))?;
+            }
+            writeln!(f)?;
+            for note in &candidate.notes {
+                writeln!(f, "         - {}", note)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::size::*;
+    use crate::parser::{parse, parse_with_lines};
+
+    #[test]
+    fn estimates_the_packages_kept() {
+        let instructions = parse("FROM python:3.7-slim AS base\n\
+                                  FROM base\n\
+                                  RUN apt-get update && apt-get install -y --no-install-recommends curl=7.88.1-10 gcc \
+                                      && pip install uwsgi && apt-get purge -y --auto-remove gcc\n\
+                                  RUN apt-get install -y git\n\
+                                  COPY . /app\n").unwrap();
+        let estimate = estimate(&instructions, &SizeTable::default());
+
+        assert_eq!(estimate.image, "python:3.7-slim");
+        assert_eq!(estimate.packages, vec![(String::from("curl"), 6 * MB), (String::from("git"), 35 * MB)]);
+        assert_eq!(estimate.unknown, vec![3, 5]);
+        assert_eq!(estimate.to_string(), "python:3.7-slim: 166 MB (125 MB base, 41 MB for curl, git), plus the layers at lines 3, 5");
+    }
+
+    #[test]
+    fn counts_the_packages_of_the_base_stages() {
+        let instructions = parse("FROM python:3.7-slim AS base\n\
+                                  RUN apt-get install -y curl\n\
+                                  FROM golang:1.22 AS build\n\
+                                  RUN apt-get install -y git\n\
+                                  FROM base\n\
+                                  CMD python app.py\n").unwrap();
+        let comparison = compare(&instructions, &["gcr.io/distroless/python3"], &SizeTable::default());
+
+        assert_eq!(comparison.current.packages, vec![(String::from("curl"), 6 * MB)]);
+        assert_eq!(comparison.candidates[0].notes[1], "no shell nor package manager: the steps at lines 2, 6 can not run");
+    }
+
+    #[test]
+    fn extends_the_table() {
+        let mut table = SizeTable::default();
+        table.extend_toml("[images.\"python:3.7-slim\"]\nsize_mb = 120.5\nlibc = \"glibc\"\n\
+                           [images.\"registry.internal/python\"]\nsize_mb = 80\nlibc = \"musl\"\nshell = false\n\
+                           [packages]\nlibpq5 = 1.5\n").unwrap();
+
+        assert_eq!(table.find("python:3.7-slim").unwrap().size, 120_500_000);
+        assert_eq!(table.find("python:3.7-slim").unwrap().runtime, None);
+        assert!(!table.find("registry.internal/python:3.12").unwrap().shell);
+        assert_eq!(table.find("docker.io/library/alpine:3.19").unwrap().libc, Libc::Musl);
+        assert_eq!(table.package_size("libpq5"), Some(1_500_000));
+        assert!(table.extend_toml("[images.alpine]\nsize = 7\n").is_err());
+    }
+
+    #[test]
+    fn notes_what_a_base_changes() {
+        let instructions = parse("FROM python:3.7-slim\nRUN apt-get install -y curl\nCMD python app.py\n").unwrap();
+        let comparison = compare(&instructions, &["python:3.7-alpine", "gcr.io/distroless/python3", "python:3.7-windowsservercore"], &SizeTable::default());
+
+        assert_eq!(comparison.candidates[0].notes, vec![
+            "musl instead of glibc: manylinux wheels and glibc binaries do not run, C extensions are built from source",
+            "the installs at line 2 use apt-get, the image has apk",
+        ]);
+        assert_eq!(comparison.candidates[1].notes, vec![
+            "python 3.11 instead of python 3.7",
+            "no shell nor package manager: the steps at lines 2, 3 can not run",
+        ]);
+        assert_eq!(comparison.candidates[2].notes, vec!["not in the size table"]);
+
+        let instructions = parse("FROM registry.internal/python:3.7\nCMD python app.py\n").unwrap();
+        let comparison = compare(&instructions, &["python:3.7-alpine"], &SizeTable::default());
+        assert_eq!(comparison.candidates[0].notes, vec!["libc change unknown: the current base image is not in the size table"]);
+    }
+
+    #[test]
+    fn reports_the_lines_of_the_source() {
+        let parsed = parse_with_lines("FROM python:3.7-slim\n\n# tools\nRUN apt-get update \\\n && apt-get install -y curl\nCOPY . /app\n").unwrap();
+        let comparison = compare_with_lines(&parsed, &["python:3.7-alpine"], &SizeTable::default());
+
+        assert_eq!(comparison.current.unknown, vec![6]);
+        assert_eq!(comparison.candidates[0].notes[1], "the installs at line 4 use apt-get, the image has apk");
+
+        let instructions = parse("FROM python:3.7-slim\nCOPY <<EOF /etc/app.conf\nport=80\nEOF\nCOPY . /app\n").unwrap();
+        assert_eq!(estimate(&instructions, &SizeTable::default()).unknown, vec![5]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Set this variable to write the current output as the new snapshot, instead of comparing against it
+pub const BLESS_VAR : &str = "DOCK_GEN_BLESS";
+
+// Compare a rendered Dockerfile with a golden file. Line endings are ignored, since git may convert them.
+pub fn assert_snapshot(actual : &str, snapshot : &Path) {
+    let actual = actual.replace("\r\n", "\n");
+
+    if env::var_os(BLESS_VAR).is_some() {
+        if let Some(parent) = snapshot.parent() {
+            fs::create_dir_all(parent).expect("failed to create the snapshot directory");
+        }
+        fs::write(snapshot, &actual).unwrap_or_else(|error| panic!("failed to bless {}: {}", snapshot.display(), error));
+        return;
+    }
+
+    let expected = match fs::read_to_string(snapshot) {
+        Ok(expected) => expected.replace("\r\n", "\n"),
+        Err(error) => panic!("failed to read snapshot {}: {}\nrun with {}=1 to create it", snapshot.display(), error, BLESS_VAR),
+    };
+
+    if expected != actual {
+        panic!("Dockerfile does not match snapshot {}\n{}\nrun with {}=1 to accept the new output",
+               snapshot.display(), line_diff(&expected, &actual), BLESS_VAR);
+    }
+}
+
+// A unified-like diff: unchanged lines are prefixed with a space, removed ones with '-' and added ones with '+'
+pub fn line_diff(expected : &str, actual : &str) -> String {
+    let old : Vec<&str> = expected.lines().collect();
+    let new : Vec<&str> = actual.lines().collect();
+
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!("  {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lengths[i][j + 1] > lengths[i + 1][j]) {
+            diff.push_str(&format!("+ {}\n", new[j]));
+            j += 1;
+        } else {
+            diff.push_str(&format!("- {}\n", old[i]));
+            i += 1;
+        }
+    }
+    diff
+}
+
+// Assert a generator, or anything displayed as a Dockerfile, matches a golden file.
+// The snapshot path is relative to the manifest directory of the calling crate.
+#[macro_export]
+macro_rules! assert_dockerfile_eq {
+    ($actual:expr, $snapshot:expr) => {
+        $crate::testing::assert_snapshot(&$actual.to_string(),
+                                         &::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join($snapshot))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        let diff = line_diff("FROM python:2.7-slim\nEXPOSE 80\n", "FROM python:3.7-slim\nEXPOSE 80\n");
+        assert_eq!(diff, "- FROM python:2.7-slim\n+ FROM python:3.7-slim\n  EXPOSE 80\n");
+    }
+}
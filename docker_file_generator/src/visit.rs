@@ -0,0 +1,218 @@
+use crate::instruction::{Command, Flag, Instruction};
+
+// A read-only pass over the instructions. Override visit_instruction to see every instruction,
+// or only the hooks of the instructions the pass cares about.
+pub trait Visitor {
+    fn visit(&mut self, instructions : &[Instruction]) {
+        for instruction in instructions {
+            self.visit_instruction(instruction);
+        }
+    }
+
+    fn visit_instruction(&mut self, instruction : &Instruction) {
+        walk_instruction(self, instruction);
+    }
+
+    fn visit_comment(&mut self, _text : &str) {}
+    fn visit_from(&mut self, _image : &str, _alias : Option<&str>, _flags : &[Flag]) {}
+    fn visit_run(&mut self, _command : &Command, _flags : &[Flag]) {}
+    fn visit_cmd(&mut self, _command : &Command) {}
+    fn visit_entrypoint(&mut self, _command : &Command) {}
+    fn visit_label(&mut self, _pairs : &[(String, String)]) {}
+    fn visit_maintainer(&mut self, _name : &str) {}
+    fn visit_expose(&mut self, _ports : &[String]) {}
+    fn visit_env(&mut self, _pairs : &[(String, String)]) {}
+    fn visit_add(&mut self, _sources : &[String], _destination : &str, _flags : &[Flag]) {}
+    fn visit_copy(&mut self, _sources : &[String], _destination : &str, _flags : &[Flag]) {}
+    fn visit_volume(&mut self, _volumes : &[String]) {}
+    fn visit_user(&mut self, _user : &str) {}
+    fn visit_work_dir(&mut self, _dir : &str) {}
+    fn visit_arg(&mut self, _name : &str, _default : Option<&str>) {}
+    fn visit_stop_signal(&mut self, _signal : &str) {}
+    fn visit_healthcheck(&mut self, _command : Option<&Command>, _flags : &[Flag]) {}
+    fn visit_shell(&mut self, _shell : &[String]) {}
+    fn visit_raw(&mut self, _line : &str) {}
+}
+
+// The default traversal: call the hook of the instruction. The trigger of an ONBUILD is visited as an instruction.
+pub fn walk_instruction<V : Visitor + ?Sized>(visitor : &mut V, instruction : &Instruction) {
+    match instruction {
+        Instruction::Empty => {},
+        Instruction::Comment(text) => visitor.visit_comment(text),
+        Instruction::From { flags, image, alias } => visitor.visit_from(image, alias.as_deref(), flags),
+        Instruction::Run { flags, command, .. } => visitor.visit_run(command, flags),
+        Instruction::Cmd(command) => visitor.visit_cmd(command),
+        Instruction::Entrypoint(command) => visitor.visit_entrypoint(command),
+        Instruction::Label(pairs) => visitor.visit_label(pairs),
+        Instruction::Maintainer(name) => visitor.visit_maintainer(name),
+        Instruction::Expose(ports) => visitor.visit_expose(ports),
+        Instruction::Env(pairs) => visitor.visit_env(pairs),
+        Instruction::Add { flags, sources, destination, .. } => visitor.visit_add(sources, destination, flags),
+        Instruction::Copy { flags, sources, destination, .. } => visitor.visit_copy(sources, destination, flags),
+        Instruction::Volume(volumes) => visitor.visit_volume(volumes),
+        Instruction::User(user) => visitor.visit_user(user),
+        Instruction::WorkDir(dir) => visitor.visit_work_dir(dir),
+        Instruction::Arg { name, default } => visitor.visit_arg(name, default.as_deref()),
+        Instruction::OnBuild(trigger) => visitor.visit_instruction(trigger),
+        Instruction::StopSignal(signal) => visitor.visit_stop_signal(signal),
+        Instruction::Healthcheck { flags, command } => visitor.visit_healthcheck(command.as_ref(), flags),
+        Instruction::Shell(shell) => visitor.visit_shell(shell),
+        Instruction::Raw(line) => visitor.visit_raw(line),
+    }
+}
+
+// A pass rewriting the instructions. The hooks edit an instruction in place;
+// override transform_instruction to remove an instruction or add new ones around it.
+pub trait Transform {
+    fn transform(&mut self, instructions : Vec<Instruction>) -> Vec<Instruction> {
+        instructions.into_iter().flat_map(|instruction| self.transform_instruction(instruction)).collect()
+    }
+
+    fn transform_instruction(&mut self, mut instruction : Instruction) -> Vec<Instruction> {
+        walk_instruction_mut(self, &mut instruction);
+        vec![instruction]
+    }
+
+    fn transform_comment(&mut self, _text : &mut String) {}
+    fn transform_from(&mut self, _image : &mut String, _alias : &mut Option<String>, _flags : &mut Vec<Flag>) {}
+    fn transform_run(&mut self, _command : &mut Command, _flags : &mut Vec<Flag>) {}
+    fn transform_cmd(&mut self, _command : &mut Command) {}
+    fn transform_entrypoint(&mut self, _command : &mut Command) {}
+    fn transform_label(&mut self, _pairs : &mut Vec<(String, String)>) {}
+    fn transform_maintainer(&mut self, _name : &mut String) {}
+    fn transform_expose(&mut self, _ports : &mut Vec<String>) {}
+    fn transform_env(&mut self, _pairs : &mut Vec<(String, String)>) {}
+    fn transform_add(&mut self, _sources : &mut Vec<String>, _destination : &mut String, _flags : &mut Vec<Flag>) {}
+    fn transform_copy(&mut self, _sources : &mut Vec<String>, _destination : &mut String, _flags : &mut Vec<Flag>) {}
+    fn transform_volume(&mut self, _volumes : &mut Vec<String>) {}
+    fn transform_user(&mut self, _user : &mut String) {}
+    fn transform_work_dir(&mut self, _dir : &mut String) {}
+    fn transform_arg(&mut self, _name : &mut String, _default : &mut Option<String>) {}
+    fn transform_stop_signal(&mut self, _signal : &mut String) {}
+    fn transform_healthcheck(&mut self, _command : &mut Option<Command>, _flags : &mut Vec<Flag>) {}
+    fn transform_shell(&mut self, _shell : &mut Vec<String>) {}
+}
+
+pub fn walk_instruction_mut<T : Transform + ?Sized>(transform : &mut T, instruction : &mut Instruction) {
+    match instruction {
+        Instruction::Comment(text) => transform.transform_comment(text),
+        Instruction::From { flags, image, alias } => transform.transform_from(image, alias, flags),
+        Instruction::Run { flags, command, .. } => transform.transform_run(command, flags),
+        Instruction::Cmd(command) => transform.transform_cmd(command),
+        Instruction::Entrypoint(command) => transform.transform_entrypoint(command),
+        Instruction::Label(pairs) => transform.transform_label(pairs),
+        Instruction::Maintainer(name) => transform.transform_maintainer(name),
+        Instruction::Expose(ports) => transform.transform_expose(ports),
+        Instruction::Env(pairs) => transform.transform_env(pairs),
+        Instruction::Add { flags, sources, destination, .. } => transform.transform_add(sources, destination, flags),
+        Instruction::Copy { flags, sources, destination, .. } => transform.transform_copy(sources, destination, flags),
+        Instruction::Volume(volumes) => transform.transform_volume(volumes),
+        Instruction::User(user) => transform.transform_user(user),
+        Instruction::WorkDir(dir) => transform.transform_work_dir(dir),
+        Instruction::Arg { name, default } => transform.transform_arg(name, default),
+        Instruction::OnBuild(trigger) => walk_instruction_mut(transform, trigger),
+        Instruction::StopSignal(signal) => transform.transform_stop_signal(signal),
+        Instruction::Healthcheck { flags, command } => transform.transform_healthcheck(command, flags),
+        Instruction::Shell(shell) => transform.transform_shell(shell),
+        Instruction::Empty | Instruction::Raw(_) => {},
+    }
+}
+
+// Transforms run one after the other, each on the output of the previous one
+#[derive(Default)]
+pub struct Pipeline {
+    passes : Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn pass<T : Transform + 'static>(&mut self, pass : T) -> &mut Pipeline {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+}
+
+impl Transform for Pipeline {
+    fn transform(&mut self, instructions : Vec<Instruction>) -> Vec<Instruction> {
+        self.passes.iter_mut().fold(instructions, |instructions, pass| pass.transform(instructions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::visit::*;
+    use crate::parser::parse;
+
+    struct Mirror;
+
+    impl Transform for Mirror {
+        fn transform_from(&mut self, image : &mut String, _alias : &mut Option<String>, _flags : &mut Vec<Flag>) {
+            if let Some(rest) = image.strip_prefix("docker.io/") {
+                *image = format!("mirror.internal/{}", rest);
+            }
+        }
+    }
+
+    struct StageLabel;
+
+    impl Transform for StageLabel {
+        fn transform_instruction(&mut self, instruction : Instruction) -> Vec<Instruction> {
+            match instruction {
+                Instruction::From { .. } => vec![instruction, Instruction::Label(vec![(String::from("team"), String::from("platform"))])],
+                instruction => vec![instruction],
+            }
+        }
+    }
+
+    struct Bash;
+
+    impl Transform for Bash {
+        fn transform_shell(&mut self, shell : &mut Vec<String>) {
+            shell[0] = String::from("/bin/bash");
+        }
+
+        fn transform_stop_signal(&mut self, signal : &mut String) {
+            *signal = signal.trim_start_matches("SIG").to_string();
+        }
+
+        fn transform_maintainer(&mut self, name : &mut String) {
+            name.make_ascii_lowercase();
+        }
+    }
+
+    #[derive(Default)]
+    struct Images(Vec<String>);
+
+    impl Visitor for Images {
+        fn visit_from(&mut self, image : &str, _alias : Option<&str>, _flags : &[Flag]) {
+            self.0.push(image.to_string());
+        }
+    }
+
+    #[test]
+    fn pipeline_runs_passes_in_order() {
+        let instructions = parse("FROM docker.io/library/rust:1 AS build\nRUN cargo build\nFROM debian:bookworm-slim\n").unwrap();
+
+        let mut pipeline = Pipeline::default();
+        pipeline.pass(Mirror).pass(StageLabel);
+        let transformed = pipeline.transform(instructions);
+
+        let rendered : Vec<String> = transformed.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(rendered, vec!["FROM mirror.internal/library/rust:1 AS build", "LABEL team=platform", "RUN cargo build",
+                                  "FROM debian:bookworm-slim", "LABEL team=platform"]);
+
+        let mut images = Images::default();
+        images.visit(&transformed);
+        assert_eq!(images.0, vec!["mirror.internal/library/rust:1", "debian:bookworm-slim"]);
+    }
+
+    #[test]
+    fn transforms_every_kind_of_instruction() {
+        let instructions = parse("MAINTAINER Team\nSHELL [\"/bin/sh\", \"-c\"]\nSTOPSIGNAL SIGINT\nONBUILD SHELL [\"/bin/sh\", \"-c\"]\n").unwrap();
+        let rendered : Vec<String> = Bash.transform(instructions).iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(rendered, vec!["MAINTAINER team", "SHELL [\"/bin/bash\", \"-c\"]", "STOPSIGNAL INT", "ONBUILD SHELL [\"/bin/bash\", \"-c\"]"]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use crate::generator::{self, DockerfileGenerator, GenerateError};
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    // How long the files must stay untouched before regenerating, so a burst of changes regenerates once
+    pub debounce : Duration,
+    // Directories whose changes are not inputs, such as build outputs
+    pub ignore   : Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            debounce: Duration::from_millis(200),
+            ignore: vec![String::from(".git"), String::from("target")],
+        }
+    }
+}
+
+// What happened after a change
+#[derive(Debug)]
+pub enum Outcome {
+    Written(Vec<PathBuf>),
+    Unchanged,
+    Failed(GenerateError),
+}
+
+// Watches files and directories, recursively, with inotify on Linux and the native API elsewhere
+pub struct Watcher {
+    // Dropping the watcher stops the notifications
+    _watcher : RecommendedWatcher,
+    events   : Receiver<notify::Result<notify::Event>>,
+    options  : Options,
+    roots    : Vec<PathBuf>,
+    skipped  : Vec<PathBuf>,
+}
+
+impl Watcher {
+    pub fn new(paths : &[&Path], options : Options) -> Result<Watcher, GenerateError> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        }).map_err(error)?;
+        let mut roots = Vec::new();
+        for path in paths {
+            watcher.watch(path, RecursiveMode::Recursive).map_err(error)?;
+            // Events name the paths as they were given, or resolved, depending on the platform
+            roots.push(path.to_path_buf());
+            roots.push(path.canonicalize().map_err(GenerateError::IO)?);
+        }
+        Ok(Watcher { _watcher: watcher, events, options, roots, skipped: Vec::new() })
+    }
+
+    // Files whose changes are ignored, such as the generated ones
+    pub fn skip(&mut self, path : &Path) {
+        if !self.skipped.iter().any(|skipped| skipped == path) {
+            self.skipped.push(path.to_path_buf());
+        }
+    }
+
+    // Block until inputs change and then stay quiet for the debounce delay, and return the changed paths.
+    // None when the timeout elapses first.
+    pub fn wait(&self, timeout : Option<Duration>) -> Result<Option<Vec<PathBuf>>, GenerateError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut changed : Vec<PathBuf> = Vec::new();
+        loop {
+            let wait = if changed.is_empty() {
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            } else {
+                Some(self.options.debounce)
+            };
+            let event = match wait {
+                Some(wait) => self.events.recv_timeout(wait),
+                None => self.events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(event) => {
+                    for path in event.map_err(error)?.paths {
+                        if self.is_input(&path) && !changed.contains(&path) {
+                            changed.push(path);
+                        }
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) if changed.is_empty() => return Ok(None),
+                Err(RecvTimeoutError::Timeout) => return Ok(Some(changed)),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(GenerateError::IO(io::Error::new(io::ErrorKind::BrokenPipe, "the file watcher stopped")));
+                },
+            }
+        }
+    }
+
+    fn is_input(&self, path : &Path) -> bool {
+        // Only the part of the path inside the watched directory may be ignored
+        let relative = self.roots.iter().find_map(|root| path.strip_prefix(root).ok()).unwrap_or(path);
+        let ignored = relative.components().any(|component| match component {
+            Component::Normal(name) => self.options.ignore.iter().any(|ignore| name == ignore.as_str()),
+            _ => false,
+        });
+        !ignored && !self.skipped.iter().any(|skipped| path.ends_with(skipped))
+    }
+}
+
+// Generate once, then again every time the watched paths change, until report returns false.
+// The generator given by the closure must have a path. Only the files whose content changes are written.
+pub fn watch<F, R>(paths : &[&Path], options : Options, mut generate : F, mut report : R) -> Result<(), GenerateError>
+    where F: FnMut() -> Result<DockerfileGenerator, GenerateError>,
+          R: FnMut(&[PathBuf], Outcome) -> bool {
+    let mut watcher = Watcher::new(paths, options)?;
+    let mut changed = Vec::new();
+    loop {
+        let outcome = match generate() {
+            Ok(mut generator) => {
+                if let Some(path) = generator.output_path() {
+                    watcher.skip(path);
+                    watcher.skip(&generator::temporary_path(path));
+                    watcher.skip(&path.with_file_name(".dockerignore"));
+                }
+                match generator.generate_changed() {
+                    Ok(written) if written.is_empty() => Outcome::Unchanged,
+                    Ok(written) => Outcome::Written(written),
+                    Err(generate_error) => Outcome::Failed(generate_error),
+                }
+            },
+            Err(generate_error) => Outcome::Failed(generate_error),
+        };
+        if !report(&changed, outcome) {
+            return Ok(());
+        }
+
+        changed = loop {
+            if let Some(changed) = watcher.wait(None)? {
+                break changed;
+            }
+        };
+    }
+}
+
+fn error(error : notify::Error) -> GenerateError {
+    match error.kind {
+        notify::ErrorKind::Io(io_error) => GenerateError::IO(io_error),
+        _ => GenerateError::IO(io::Error::other(error.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+
+    use crate::watch::*;
+
+    #[test]
+    fn debounces_a_burst_of_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("target")).unwrap();
+        let mut watcher = Watcher::new(&[dir.path()], Options::default()).unwrap();
+        watcher.skip(&dir.path().join("Dockerfile"));
+
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM scratch").unwrap();
+        fs::write(dir.path().join("target/build.log"), "").unwrap();
+
+        let mut changed = watcher.wait(Some(Duration::from_secs(5))).unwrap().unwrap();
+        changed.sort();
+        let names : Vec<String> = changed.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, vec!["Cargo.toml", "main.rs"]);
+
+        assert_eq!(watcher.wait(Some(Duration::from_millis(300))).unwrap(), None);
+    }
+
+    #[test]
+    fn writes_only_what_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Dockerfile");
+        let generator = |image : &str| {
+            let mut generator = DockerfileGenerator::default();
+            generator.path(path.clone()).ignore("target").from(image);
+            generator
+        };
+
+        assert_eq!(generator("alpine").generate_changed().unwrap(), vec![path.clone(), dir.path().join(".dockerignore")]);
+        assert!(generator("alpine").generate_changed().unwrap().is_empty());
+        assert_eq!(generator("debian").generate_changed().unwrap(), vec![path.clone()]);
+    }
+
+    #[test]
+    fn regenerating_the_same_output_touches_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Dockerfile");
+        let generator = || {
+            let mut generator = DockerfileGenerator::default();
+            generator.path(path.clone()).from("alpine").run("apk add curl");
+            generator
+        };
+        generator().generate().unwrap();
+
+        let mut watcher = Watcher::new(&[dir.path()], Options::default()).unwrap();
+        watcher.skip(&path);
+        assert!(generator().generate_changed().unwrap().is_empty());
+        assert_eq!(watcher.wait(Some(Duration::from_millis(300))).unwrap(), None);
+        assert!(!generator::temporary_path(&path).exists());
+
+        // A file which only starts with the output is still rewritten
+        let output = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("{}USER nobody\r\n", output)).unwrap();
+        assert_eq!(generator().generate_changed().unwrap(), vec![path.clone()]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), output);
+    }
+}
@@ -0,0 +1,23 @@
+use std::fs;
+
+use dock_gen::assert_dockerfile_eq;
+use dock_gen::codegen;
+use dock_gen::parser::parse;
+
+// Generated by `dock-gen rust tests/snapshots/codegen.Dockerfile`, and compiled here
+#[path = "snapshots/codegen.rs"]
+mod generated;
+
+const DOCKERFILE : &str = "tests/snapshots/codegen.Dockerfile";
+
+#[test]
+fn generates_the_builder_code() {
+    let source = fs::read_to_string(DOCKERFILE).unwrap();
+    let instructions = parse(&source).unwrap();
+    assert_dockerfile_eq!(codegen::to_rust(&instructions, "dockerfile"), "tests/snapshots/codegen.rs");
+}
+
+#[test]
+fn generated_code_reproduces_the_dockerfile() {
+    assert_dockerfile_eq!(generated::dockerfile().build(), DOCKERFILE);
+}
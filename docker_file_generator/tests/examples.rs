@@ -0,0 +1,36 @@
+use dock_gen::assert_dockerfile_eq;
+use dock_gen::size::SizeTable;
+
+#[path = "../examples/simple.rs"]
+#[allow(dead_code)]
+mod simple;
+
+#[path = "../examples/dynamic.rs"]
+#[allow(dead_code)]
+mod dynamic;
+
+#[path = "../examples/rust_project.rs"]
+#[allow(dead_code)]
+mod rust_project;
+
+#[test]
+fn simple_example() {
+    assert_dockerfile_eq!(simple::dockerfile().build().render().unwrap(), "tests/snapshots/simple.Dockerfile");
+}
+
+#[test]
+fn dynamic_example() {
+    assert_dockerfile_eq!(dynamic::dockerfile(2).build(), "tests/snapshots/dynamic-2.Dockerfile");
+    assert_dockerfile_eq!(dynamic::dockerfile(3).build(), "tests/snapshots/dynamic-3.Dockerfile");
+}
+
+#[test]
+fn dynamic_example_bases() {
+    let comparison = dynamic::dockerfile(3).build().compare_bases(&dynamic::CANDIDATES, &SizeTable::default());
+    assert_dockerfile_eq!(comparison.to_string(), "tests/snapshots/dynamic-bases.txt");
+}
+
+#[test]
+fn rust_project_example() {
+    assert_dockerfile_eq!(rust_project::dockerfile("overload_test").unwrap(), "tests/snapshots/rust-overload_test.Dockerfile");
+}
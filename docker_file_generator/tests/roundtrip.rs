@@ -0,0 +1,196 @@
+use dock_gen::generator::DockerfileGenerator;
+use dock_gen::instruction::{Command, Flag, Heredoc, Instruction};
+use dock_gen::parser::{parse, parse_line};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+// A Dockerfile the way the generator writes it, one instruction per line
+fn render(instructions : &[Instruction]) -> String {
+    instructions.iter().map(|instruction| format!("{}\n", instruction)).collect()
+}
+
+fn word() -> impl Strategy<Value = String> {
+    "[a-z0-9][a-z0-9._/:@-]{0,20}"
+}
+
+fn key() -> impl Strategy<Value = String> {
+    "[A-Za-z_][A-Za-z0-9_.-]{0,12}"
+}
+
+// Values of ENV, LABEL and ARG: anything, with quotes, backslashes, escaped $, unicode and newlines
+fn value() -> impl Strategy<Value = String> {
+    "(\\\\\\$|\\\\|\\$|\"|[ -~]|\t|\n|é|\u{a0}|\u{3000}){0,30}"
+}
+
+// The values a single line can hold
+fn line_value() -> impl Strategy<Value = String> {
+    value().prop_filter("a newline ends the line", |value| !value.contains('\n'))
+}
+
+// What a value of ENV means: its characters, each `$` telling whether it is literal or expanded by the build
+fn meaning() -> impl Strategy<Value = Vec<(char, bool)>> {
+    vec(("[ -~\t\u{e9}\u{a0}]|\\$", any::<bool>()), 0..20).prop_map(|characters| {
+        characters.into_iter().map(|(c, literal)| {
+            let c = c.chars().next().unwrap();
+            (c, c == '$' && literal)
+        }).collect()
+    })
+}
+
+// The value written the way people do: bare, in double quotes or in single quotes, when it can be
+fn written(meaning : &[(char, bool)], style : u8) -> String {
+    let expanded = meaning.iter().any(|&(c, literal)| c == '$' && !literal);
+    let special = meaning.iter().any(|&(c, _)| c.is_whitespace() || c == '\\');
+    if style == 2 && !expanded && !meaning.iter().any(|&(c, _)| c == '\'') {
+        return format!("'{}'", meaning.iter().map(|&(c, _)| c).collect::<String>());
+    }
+    let bare = style == 0 && !special;
+    let mut written = String::new();
+    for &(c, literal) in meaning {
+        if (c == '$' && literal) || c == '"' || (c == '\\' && !bare) || (c == '\'' && bare) {
+            written.push('\\');
+        }
+        written.push(c);
+    }
+    if bare { written } else { format!("\"{}\"", written) }
+}
+
+// How the value is written once formatted: bare unless it has to be quoted
+fn canonical(meaning : &[(char, bool)]) -> String {
+    let bare = !meaning.is_empty() && !meaning.iter().any(|&(c, _)| c.is_whitespace() || c == '"' || c == '\'' || c == '\\');
+    let mut written = String::new();
+    for &(c, literal) in meaning {
+        if (c == '$' && literal) || (!bare && (c == '"' || c == '\\')) {
+            written.push('\\');
+        }
+        written.push(c);
+    }
+    if bare { written } else { format!("\"{}\"", written) }
+}
+
+fn flag() -> impl Strategy<Value = Flag> {
+    ("[a-z][a-z-]{0,10}", option::of("[a-zA-Z0-9=/._:,-]{0,16}"))
+        .prop_map(|(name, value)| Flag { name, value })
+}
+
+// A shell command on a single line, whose quotes, backslashes and variables are left to the shell.
+// It can not end with a backslash, which continues the line, nor start the way flags or the exec form do.
+// Its `<<` are shifts, quoted text and here-strings, never the marker of a heredoc, and its quotes are balanced.
+fn shell() -> impl Strategy<Value = String> {
+    let chunk = prop_oneof![
+        4 => "[ !#-&(-;=-~]{0,8}[!#-&(-;=-\\[\\]-~]",
+        1 => "\"[ !#-\\[\\]-~]{0,8}\"",
+        1 => "'[ -&(-~]{0,8}'",
+        1 => Just(String::from("$((1<<3))")),
+        1 => Just(String::from("$(( 1 << 3 ))")),
+        1 => "\"[a-z ]*<<[A-Z]*\"",
+        1 => "'[a-z ]*<<-?[A-Z]*'",
+        1 => "<<<[a-z]+",
+        1 => "[a-z]+<<[A-Z]+",
+    ];
+    ("[!#-&(-,.-;=-Z^-~]", vec(chunk, 0..6)).prop_map(|(first, chunks)| format!("{}{}", first, chunks.concat()))
+}
+
+fn exec() -> impl Strategy<Value = Vec<String>> {
+    vec(any::<String>(), 0..4)
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    prop_oneof![
+        shell().prop_map(Command::Shell),
+        exec().prop_map(Command::Exec),
+    ]
+}
+
+fn path() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z0-9_.*/][a-zA-Z0-9_.*/-]{0,16}",
+        // Written in the JSON form
+        "([a-zA-Z0-9_.*/ \"\\\\][a-zA-Z0-9_.*/ \"\\\\-]{0,15})?",
+    ]
+}
+
+fn heredoc() -> impl Strategy<Value = Heredoc> {
+    vec("[^\r\n]{0,20}", 0..4).prop_map(|lines| Heredoc::new(&lines.join("\n")))
+}
+
+// Instructions a single line can hold, which ONBUILD can trigger
+fn line() -> impl Strategy<Value = Instruction> {
+    prop_oneof![
+        (vec(flag(), 0..2), command()).prop_map(|(flags, command)| Instruction::Run { flags, command, heredocs: Vec::new() }),
+        command().prop_map(Instruction::Cmd),
+        command().prop_map(Instruction::Entrypoint),
+        vec((key(), line_value()), 1..4).prop_map(Instruction::Label),
+        vec((key(), line_value()), 1..4).prop_map(Instruction::Env),
+        vec("[0-9]{1,5}(/tcp|/udp)?", 1..4).prop_map(Instruction::Expose),
+        (vec(flag(), 0..2), vec(path(), 1..3), path())
+            .prop_map(|(flags, sources, destination)| Instruction::Copy { flags, sources, destination, heredocs: Vec::new() }),
+        (vec(flag(), 0..2), vec(path(), 1..3), path())
+            .prop_map(|(flags, sources, destination)| Instruction::Add { flags, sources, destination, heredocs: Vec::new() }),
+        vec(path(), 1..3).prop_map(Instruction::Volume),
+        word().prop_map(Instruction::User),
+        word().prop_map(Instruction::WorkDir),
+        word().prop_map(Instruction::StopSignal),
+        ("[A-Za-z_][A-Za-z0-9_]{0,12}", option::of(line_value())).prop_map(|(name, default)| Instruction::Arg { name, default }),
+        (vec(flag(), 0..2), option::of(command())).prop_map(|(flags, command)| Instruction::Healthcheck { flags, command }),
+        exec().prop_map(Instruction::Shell),
+    ]
+}
+
+fn instruction() -> impl Strategy<Value = Instruction> {
+    prop_oneof![
+        Just(Instruction::Empty),
+        "([!-~]([ -~]{0,30}[!-~])?)?".prop_map(Instruction::Comment),
+        (vec(flag(), 0..2), word(), option::of("[a-z][a-z0-9_-]{0,10}"))
+            .prop_map(|(flags, image, alias)| Instruction::From { flags, image, alias }),
+        "[a-zA-Z0-9<>@.]([a-zA-Z0-9 <>@.]{0,20}[a-zA-Z0-9>])?".prop_map(Instruction::Maintainer),
+        line(),
+        line().prop_map(|trigger| Instruction::OnBuild(Box::new(trigger))),
+        ("(cat|python3|sh -e)", vec(heredoc(), 1..3)).prop_map(|(program, heredocs)| {
+            let markers : Vec<String> = heredocs.iter().map(Heredoc::marker).collect();
+            let command = Command::Shell(format!("{} {}", program, markers.join(" ")));
+            Instruction::Run { flags: Vec::new(), command, heredocs }
+        }),
+        (heredoc(), path()).prop_map(|(heredoc, destination)| {
+            Instruction::Copy { flags: Vec::new(), sources: vec![heredoc.marker()], destination, heredocs: vec![heredoc] }
+        }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn parses_what_it_renders(instructions in vec(instruction(), 0..12)) {
+        prop_assert_eq!(parse(&render(&instructions)).unwrap(), instructions);
+    }
+
+    #[test]
+    fn escapes_env_and_run_values(key in key(), value in line_value(), line in shell()) {
+        let env = Instruction::Env(vec![(key, value)]);
+        prop_assert_eq!(parse_line(&env.to_string()).unwrap(), env);
+
+        let run = Instruction::Run { flags: Vec::new(), command: Command::Shell(line), heredocs: Vec::new() };
+        prop_assert_eq!(parse_line(&run.to_string()).unwrap(), run);
+    }
+
+    #[test]
+    fn rejects_values_a_line_can_not_hold(key in key(), value in value()) {
+        let mut generator = DockerfileGenerator::default();
+        generator.label(&key, &value);
+        match generator.render() {
+            Ok(rendered) => prop_assert_eq!(parse(&rendered).unwrap(), vec![Instruction::Label(vec![(key, value)])]),
+            Err(_) => prop_assert!(value.contains('\n')),
+        }
+    }
+
+    #[test]
+    fn keeps_what_values_mean(meaning in meaning(), style in 0..3u8) {
+        let source = format!("ENV A={}\n", written(&meaning, style));
+        prop_assert_eq!(render(&parse(&source).unwrap()), format!("ENV A={}\n", canonical(&meaning)));
+    }
+
+    #[test]
+    fn never_panics_on_any_input(source in "(RUN|ENV|ONBUILD|COPY|ARG|#|<<|\\\\|\"|\\[|\n|[ -~])*") {
+        let _ = parse(&source);
+    }
+}
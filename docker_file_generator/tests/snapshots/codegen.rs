@@ -0,0 +1,29 @@
+use dock_gen::builder::DockerfileBuilder;
+use dock_gen::generator::DockerfileGenerator;
+#[allow(unused_imports)]
+use dock_gen::instruction::{Command, Flag, Heredoc, Instruction};
+
+pub fn dockerfile() -> DockerfileBuilder {
+    DockerfileGenerator::builder()
+        .comment("syntax=docker/dockerfile:1")
+        .arg("VERSION=3.7")
+        .from("python:${VERSION}-slim AS base")
+        .empty_line()
+        .comment("Dependencies first, for the cache")
+        .work_dir("/app")
+        .copy("--chown=app:app requirements.txt", "./")
+        .run("--mount=type=cache,target=/root/.cache pip install -r requirements.txt")
+        .push_instruction(Instruction::Run { flags: vec![], command: Command::Shell(String::from(r#"<<"EOF1""#)), heredocs: vec![Heredoc { delimiter: String::from("EOF1"), body: String::from("set -e\necho \"EOF\" > /tmp/marker\n") }] })
+        .copy_content("path = \"C:\\app\"\n", "/etc/app.conf")
+        .push(r#"COPY ["my file.txt", "/app/"]"#)
+        .push(r#"ENV GREETING="hello \"world\"" PATH=/app/bin:$PATH"#)
+        .env("NAME", "World")
+        .label("org.opencontainers.image.title", "demo app")
+        .expose(80)
+        .push("EXPOSE 53/udp")
+        .push("ONBUILD RUN make")
+        .healthcheck("--interval=30s CMD curl -f http://localhost/ || exit 1")
+        .user("app")
+        .stop_signal("SIGTERM")
+        .cmd(r#"["python", "app.py"]"#)
+}
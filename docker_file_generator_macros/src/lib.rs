@@ -0,0 +1,229 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{bracketed, parse_macro_input, Error, Expr, ExprLit, Ident, Lit, LitStr, Token};
+
+const INSTRUCTIONS : &[&str] = &["FROM", "WORKDIR", "COPY", "ADD", "RUN", "EXPOSE", "ENV", "ARG", "LABEL", "USER", "VOLUME", "CMD", "ENTRYPOINT",
+                                  "HEALTHCHECK", "STOPSIGNAL"];
+
+// Instructions of a Dockerfile the macro has no statement for
+const UNSUPPORTED : &[&str] = &["MAINTAINER", "ONBUILD", "SHELL"];
+
+// Either the shell form (a single expression) or the exec form (a bracketed list of strings)
+enum CommandArg {
+    Shell(Expr),
+    Exec(Vec<Expr>),
+}
+
+enum Statement {
+    Comment(LitStr),
+    From(Expr),
+    WorkDir(Expr),
+    Copy(Expr, Expr),
+    Add(Expr, Expr),
+    Run(CommandArg),
+    Expose(Expr),
+    Env(Ident, Expr),
+    Arg(Expr),
+    // The key of a label is a string when it is not an identifier, as org.opencontainers.image.source
+    Label(String, Expr),
+    User(Expr),
+    Volume(Expr),
+    Cmd(CommandArg),
+    Entrypoint(CommandArg),
+    Healthcheck(Expr),
+    StopSignal(Expr),
+}
+
+struct Dockerfile {
+    statements : Vec<Statement>,
+}
+
+impl Parse for CommandArg {
+    fn parse(input : ParseStream) -> syn::Result<CommandArg> {
+        if !input.peek(syn::token::Bracket) {
+            return Ok(CommandArg::Shell(input.parse()?));
+        }
+
+        let content;
+        let brackets = bracketed!(content in input);
+        let elements = Punctuated::<Expr, Token![,]>::parse_terminated(&content)?;
+        if elements.is_empty() {
+            return Err(Error::new(brackets.span.join(), "exec form requires at least one element"));
+        }
+
+        for element in &elements {
+            if let Expr::Lit(ExprLit { lit, .. }) = element {
+                if !matches!(lit, Lit::Str(_)) {
+                    return Err(Error::new(lit.span(), "exec form elements must be strings"));
+                }
+            }
+        }
+
+        Ok(CommandArg::Exec(elements.into_iter().collect()))
+    }
+}
+
+impl Parse for Statement {
+    fn parse(input : ParseStream) -> syn::Result<Statement> {
+        let statement = if input.peek(Token![#]) {
+            input.parse::<Token![#]>()?;
+            Statement::Comment(input.parse()?)
+        } else {
+            Statement::parse_instruction(input)?
+        };
+
+        if !input.is_empty() {
+            input.parse::<Token![;]>()?;
+        }
+        Ok(statement)
+    }
+}
+
+impl Statement {
+    fn parse_instruction(input : ParseStream) -> syn::Result<Statement> {
+        let keyword : Ident = input.parse()?;
+        let statement = match keyword.to_string().to_uppercase().as_str() {
+            "FROM" => Statement::From(input.parse()?),
+            "WORKDIR" => Statement::WorkDir(input.parse()?),
+            "COPY" => Statement::Copy(input.parse()?, input.parse()?),
+            "ADD" => Statement::Add(input.parse()?, input.parse()?),
+            "RUN" => Statement::Run(input.parse()?),
+            "EXPOSE" => Statement::Expose(input.parse()?),
+            "ENV" => {
+                let key = input.parse()?;
+                input.parse::<Option<Token![=]>>()?;
+                Statement::Env(key, input.parse()?)
+            },
+            "ARG" => Statement::Arg(input.parse()?),
+            "LABEL" => {
+                let key = if input.peek(LitStr) { input.parse::<LitStr>()?.value() } else { input.parse::<Ident>()?.to_string() };
+                input.parse::<Option<Token![=]>>()?;
+                Statement::Label(key, input.parse()?)
+            },
+            "USER" => Statement::User(input.parse()?),
+            "VOLUME" => Statement::Volume(input.parse()?),
+            "CMD" => Statement::Cmd(input.parse()?),
+            "ENTRYPOINT" => Statement::Entrypoint(input.parse()?),
+            "HEALTHCHECK" => Statement::Healthcheck(input.parse()?),
+            "STOPSIGNAL" => Statement::StopSignal(input.parse()?),
+            name if UNSUPPORTED.contains(&name) => return Err(Error::new(keyword.span(),
+                                       format!("`{}` is not supported by dockerfile!, expected one of: {}", keyword, INSTRUCTIONS.join(", ")))),
+            _ => return Err(Error::new(keyword.span(),
+                                       format!("unknown Dockerfile instruction `{}`, expected one of: {}", keyword, INSTRUCTIONS.join(", ")))),
+        };
+        Ok(statement)
+    }
+
+    fn expand(&self, generator : &Ident) -> TokenStream2 {
+        match self {
+            Statement::Comment(line) => quote! { #generator.comment(#line); },
+            Statement::From(image) => {
+                let image = text(image);
+                quote! { #generator.from(#image); }
+            },
+            Statement::WorkDir(dir) => {
+                let dir = text(dir);
+                quote! { #generator.work_dir(#dir); }
+            },
+            Statement::Copy(from, to) => {
+                let (from, to) = (text(from), text(to));
+                quote! { #generator.copy(#from, #to); }
+            },
+            Statement::Add(from, to) => {
+                let (from, to) = (text(from), text(to));
+                quote! { #generator.add(#from, #to); }
+            },
+            Statement::Run(arg) => {
+                let line = command(arg);
+                quote! { #generator.run(#line); }
+            },
+            Statement::Expose(port) => quote_spanned! {port.span()=> #generator.expose(#port); },
+            Statement::Env(key, value) => {
+                let key = key.to_string();
+                let value = text(value);
+                quote! { #generator.env(#key, #value); }
+            },
+            Statement::Arg(line) => {
+                let line = text(line);
+                quote! { #generator.arg(#line); }
+            },
+            Statement::Label(key, value) => {
+                let value = text(value);
+                quote! { #generator.label(#key, #value); }
+            },
+            Statement::User(user) => {
+                let user = text(user);
+                quote! { #generator.user(#user); }
+            },
+            Statement::Volume(volume) => {
+                let volume = text(volume);
+                quote! { #generator.volume(#volume); }
+            },
+            Statement::Cmd(arg) => {
+                let line = command(arg);
+                quote! { #generator.cmd(#line); }
+            },
+            Statement::Entrypoint(arg) => {
+                let line = command(arg);
+                quote! { #generator.entrypoint(#line); }
+            },
+            Statement::Healthcheck(line) => {
+                let line = text(line);
+                quote! { #generator.healthcheck(#line); }
+            },
+            Statement::StopSignal(signal) => {
+                let signal = text(signal);
+                quote! { #generator.stop_signal(#signal); }
+            },
+        }
+    }
+}
+
+impl Parse for Dockerfile {
+    fn parse(input : ParseStream) -> syn::Result<Dockerfile> {
+        let mut statements = Vec::new();
+        while !input.is_empty() {
+            statements.push(input.parse()?);
+        }
+        Ok(Dockerfile { statements })
+    }
+}
+
+fn text(expr : &Expr) -> TokenStream2 {
+    quote_spanned! {expr.span()=> &::std::string::ToString::to_string(&(#expr))}
+}
+
+fn command(arg : &CommandArg) -> TokenStream2 {
+    match arg {
+        CommandArg::Shell(expr) => text(expr),
+        CommandArg::Exec(elements) => {
+            let elements = elements.iter().map(|element| {
+                quote_spanned! {element.span()=> ::std::string::ToString::to_string(&(#element))}
+            });
+            quote! { &::dock_gen::generator::exec_form(&[#(#elements),*]) }
+        },
+    }
+}
+
+// Build a DockerfileGenerator from an inline Dockerfile, e.g.
+// dockerfile! { FROM "python:3.7-slim"; WORKDIR "/app"; EXPOSE 80; CMD ["python", "app.py"]; }
+#[proc_macro]
+pub fn dockerfile(input : TokenStream) -> TokenStream {
+    let dockerfile = parse_macro_input!(input as Dockerfile);
+    let generator = Ident::new("__dock_gen_generator", Span::mixed_site());
+    let statements = dockerfile.statements.iter().map(|statement| statement.expand(&generator));
+
+    TokenStream::from(quote! {
+        {
+            let mut #generator = ::dock_gen::generator::DockerfileGenerator::default();
+            #(#statements)*
+            #generator
+        }
+    })
+}
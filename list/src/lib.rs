@@ -1,17 +1,16 @@
-use std::cell::{RefCell};
+use std::cell::{Ref, RefCell, RefMut};
+use std::mem::ManuallyDrop;
+use std::ptr;
 use std::rc::Rc;
-use std::fmt::Debug;
 
 #[derive(Debug)]
-pub struct Node<T>
-    where T: Clone + Debug{
+pub struct Node<T> {
     next : Option<Rc<RefCell<Node<T>>>>,
     prev : Option<Rc<RefCell<Node<T>>>>,
     value : T,
 }
 
-impl <T> Node<T>
-    where T: Clone + Debug{
+impl <T> Node<T> {
     pub fn new(value: T) -> Node<T> {
         Node {
             next:None,
@@ -20,22 +19,56 @@ impl <T> Node<T>
         }
     }
 
-    pub fn value(&self) -> T {
-        self.value.clone()
+    pub fn value_ref(&self) -> &T {
+        &self.value
+    }
+
+    pub fn value_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    // Run the closure on the value, without cloning it
+    pub fn with_value<R, F>(&self, f: F) -> R
+        where F: FnOnce(&T) -> R {
+        f(&self.value)
+    }
+
+    pub fn with_value_mut<R, F>(&mut self, f: F) -> R
+        where F: FnOnce(&mut T) -> R {
+        f(&mut self.value)
+    }
+
+    // Take the value back, dropping the links to the neighbours
+    pub fn into_value(self) -> T {
+        let mut node = ManuallyDrop::new(self);
+        node.next = None;
+        node.prev = None;
+        println!("Dropping Node");
+        // The node is never dropped, so the value is only read out once
+        unsafe { ptr::read(&node.value) }
+    }
+
+    // Borrow the value of a node shared by the list, for as long as the Ref lives
+    pub fn borrow_value(node: &Rc<RefCell<Node<T>>>) -> Ref<'_, T> {
+        Ref::map(node.borrow(), |node| &node.value)
+    }
+
+    pub fn borrow_value_mut(node: &Rc<RefCell<Node<T>>>) -> RefMut<'_, T> {
+        RefMut::map(node.borrow_mut(), |node| &mut node.value)
+    }
+
+    // Take the value of a node removed from the list. Fails, giving the node back, while it is still shared.
+    pub fn try_into_value(node: Rc<RefCell<Node<T>>>) -> Result<T, Rc<RefCell<Node<T>>>> {
+        Rc::try_unwrap(node).map(|node| node.into_inner().into_value())
     }
 
     pub fn prev(&mut self) -> Option<Rc<RefCell<Node<T>>>> {
-        match &self.prev{
-            Some(x) => Some(Rc::clone(&x)),
-            None => None
-        }
+        self.prev.as_ref().map(Rc::clone)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Rc<RefCell<Node<T>>>> {
-        match &self.next{
-            Some(x) => Some(Rc::clone(&x)),
-            None => None
-        }
+        self.next.as_ref().map(Rc::clone)
     }
 
     // Set the next element, and return a mutable reference to self
@@ -85,20 +118,24 @@ impl <T> Node<T>
     }
 }
 
-impl<T> Drop for Node<T>
-    where T: Clone + Debug {
+impl <T> Node<T>
+    where T: Clone {
+    pub fn value(&self) -> T {
+        self.value.clone()
+    }
+}
+
+impl<T> Drop for Node<T> {
     fn drop(&mut self) {
         println!("Dropping Node");
     }
 }
 
-pub struct NodeIterator<T>
-    where T: Clone + Debug{
+pub struct NodeIterator<T> {
     node : Option<Rc<RefCell<Node<T>>>>,
 }
 
-impl <T> Iterator for NodeIterator<T>
-    where T: Clone + Debug{
+impl <T> Iterator for NodeIterator<T> {
     type Item = Rc<RefCell<Node<T>>>;
 
     fn next(&mut self) ->  Option<Rc<RefCell<Node<T>>>> {
@@ -116,14 +153,12 @@ impl <T> Iterator for NodeIterator<T>
  * 1) Both head and tail are either empty, or contain a node.
  * 2) The list is not cyclic.
  */
-pub struct List<T>
-    where T: Clone + Debug{
+pub struct List<T> {
     head: Option<Rc<RefCell<Node<T>>>>,
     tail: Option<Rc<RefCell<Node<T>>>>,
 }
 
-impl <T> List<T>
-    where T: Clone + Debug{
+impl <T> List<T> {
     pub fn new() -> List<T> {
         List {
             head : None,
@@ -139,6 +174,22 @@ impl <T> List<T>
         self.tail.clone()
     }
 
+    pub fn head_ref(&self) -> Option<Ref<'_, T>> {
+        self.head.as_ref().map(Node::borrow_value)
+    }
+
+    pub fn head_mut(&self) -> Option<RefMut<'_, T>> {
+        self.head.as_ref().map(Node::borrow_value_mut)
+    }
+
+    pub fn tail_ref(&self) -> Option<Ref<'_, T>> {
+        self.tail.as_ref().map(Node::borrow_value)
+    }
+
+    pub fn tail_mut(&self) -> Option<RefMut<'_, T>> {
+        self.tail.as_ref().map(Node::borrow_value_mut)
+    }
+
     pub fn remove(&mut self, node: Option<Rc<RefCell<Node<T>>>>) -> Option<Rc<RefCell<Node<T>>>> {
         if let Some(node_to_remove) = node {
             for n in &*self {
@@ -169,13 +220,6 @@ impl <T> List<T>
         }
     }
 
-    pub fn head_value(&self) -> Option<T> {
-        match &self.head {
-            Some(node) => Some(node.borrow().value()),
-            None => None
-        }
-    }
-
     pub fn push_back(&mut self, value : T) -> &mut List<T> {
         let node = Rc::new(RefCell::new(Node::new(value)));
 
@@ -195,8 +239,20 @@ impl <T> List<T>
     }
 }
 
-impl<T> Drop for List<T>
-    where T: Clone + Debug {
+impl <T> List<T>
+    where T: Clone {
+    pub fn head_value(&self) -> Option<T> {
+        self.head.as_ref().map(|node| node.borrow().value())
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> List<T> {
+        List::new()
+    }
+}
+
+impl<T> Drop for List<T> {
     fn drop(&mut self) {
         while self.head.is_some() {
             self.remove(Some(self.head().unwrap()));
@@ -206,8 +262,7 @@ impl<T> Drop for List<T>
     }
 }
 
-impl<T> IntoIterator for List<T>
-    where T: Clone + Debug {
+impl<T> IntoIterator for List<T> {
     type Item = Rc<RefCell<Node<T>>>;
     type IntoIter = NodeIterator<T>;
 
@@ -218,8 +273,7 @@ impl<T> IntoIterator for List<T>
     }
 }
 
-impl <T> IntoIterator for &List<T>
-    where T: Clone + Debug {
+impl <T> IntoIterator for &List<T> {
     type Item = Rc<RefCell<Node<T>>>;
     type IntoIter = NodeIterator<T>;
 
@@ -230,8 +284,7 @@ impl <T> IntoIterator for &List<T>
     }
 }
 
-impl <T> IntoIterator for &mut List<T>
-    where T: Clone + Debug {
+impl <T> IntoIterator for &mut List<T> {
     type Item = Rc<RefCell<Node<T>>>;
     type IntoIter = NodeIterator<T>;
 
@@ -273,4 +326,31 @@ mod tests {
 
         println!("Done");
     }
+
+    // Neither Clone nor Debug
+    struct Buffer(Vec<u8>);
+
+    #[test]
+    fn borrows_values_without_cloning() {
+        let mut list = List::new();
+        list.push_back(Buffer(vec![1, 2]))
+            .push_back(Buffer(vec![3]));
+
+        assert_eq!(list.head_ref().unwrap().0, vec![1, 2]);
+        list.tail_mut().unwrap().0.push(4);
+        let lengths : Vec<usize> = list.into_iter().map(|node| node.borrow().with_value(|buffer| buffer.0.len())).collect();
+        assert_eq!(lengths, vec![2, 2]);
+
+        let mut list = List::new();
+        list.push_back(Buffer(vec![5]))
+            .push_back(Buffer(vec![6]));
+        Node::borrow_value_mut(&list.head().unwrap()).0.push(7);
+
+        let head = list.remove(list.head()).unwrap();
+        assert_eq!(Node::try_into_value(head).ok().unwrap().0, vec![5, 7]);
+        let tail = list.tail().unwrap();
+        let tail = Node::try_into_value(tail).err().unwrap();
+        drop(list);
+        assert_eq!(Node::try_into_value(tail).ok().unwrap().0, vec![6]);
+    }
 }